@@ -1,8 +1,6 @@
 use oci_distribution::{secrets::RegistryAuth, Client, Reference};
 
 use clap::Parser;
-use docker_credential::{CredentialRetrievalError, DockerCredential};
-use tracing::{debug, warn};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter};
 
@@ -32,28 +30,12 @@ pub(crate) struct Cli {
 }
 
 fn build_auth(reference: &Reference, cli: &Cli) -> RegistryAuth {
-    let server = reference
-        .resolve_registry()
-        .strip_suffix("/")
-        .unwrap_or_else(|| reference.resolve_registry());
-
     if cli.anonymous {
         return RegistryAuth::Anonymous;
     }
 
-    match docker_credential::get_credential(server) {
-        Err(CredentialRetrievalError::ConfigNotFound) => RegistryAuth::Anonymous,
-        Err(CredentialRetrievalError::NoCredentialConfigured) => RegistryAuth::Anonymous,
-        Err(e) => panic!("Error handling docker configuration file: {}", e),
-        Ok(DockerCredential::UsernamePassword(username, password)) => {
-            debug!("Found docker credentials");
-            RegistryAuth::Basic(username, password)
-        }
-        Ok(DockerCredential::IdentityToken(_)) => {
-            warn!("Cannot use contents of docker config, identity token not supported. Using anonymous auth");
-            RegistryAuth::Anonymous
-        }
-    }
+    RegistryAuth::from_docker_config(reference.resolve_registry())
+        .expect("Error handling docker configuration file")
 }
 
 fn build_client_config(cli: &Cli) -> oci_distribution::client::ClientConfig {
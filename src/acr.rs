@@ -0,0 +1,415 @@
+//! Authentication against [Azure Container Registry](https://azure.microsoft.com/products/container-registry)
+//!
+//! ACR accepts Microsoft Entra ID (formerly Azure Active Directory) identities:
+//! an Entra ID access token is exchanged for a registry refresh token, through
+//! the `/oauth2/exchange` endpoint of the registry. [`AcrAuthProvider`]
+//! performs this exchange automatically for the `*.azurecr.io` registries, and
+//! the ones of the national clouds, and caches the refresh token until it
+//! expires. The Entra ID access tokens are requested from the cloud of the
+//! registry: `*.azurecr.cn` registries use the Azure China cloud, and
+//! `*.azurecr.us` registries the Azure US Government cloud.
+//!
+//! The refresh tokens are returned as [`RegistryAuth::IdentityToken`], which
+//! the [`Client`](crate::Client) exchanges for access tokens scoped to the
+//! pulled repositories.
+//!
+//! The Entra ID access tokens are obtained with an [`AzureCredential`]: either
+//! the managed identity of the virtual machine, as exposed by the instance
+//! metadata service, or a service principal and its client secret.
+//!
+//! [`AcrAuthProvider`] implements [`RegistryAuthProvider`], and can be added
+//! to a [`ChainedAuthProvider`](crate::secrets::ChainedAuthProvider) ahead of
+//! the docker configuration.
+//!
+//! This module is available when the `acr` feature is enabled.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::errors::{OciDistributionError, Result};
+use crate::secrets::{RegistryAuth, RegistryAuthProvider};
+
+/// The endpoint of the instance metadata service issuing the tokens of
+/// managed identities
+const IMDS_TOKEN_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+/// The Azure clouds: the suffix of their registries, the authority issuing
+/// the tokens of service principals, and the resource the Entra ID access
+/// tokens are requested for
+const CLOUDS: &[AzureCloud] = &[
+    AzureCloud {
+        registry_suffix: ".azurecr.io",
+        authority_host: "https://login.microsoftonline.com",
+        resource: "https://management.azure.com/",
+    },
+    AzureCloud {
+        registry_suffix: ".azurecr.cn",
+        authority_host: "https://login.chinacloudapi.cn",
+        resource: "https://management.chinacloudapi.cn/",
+    },
+    AzureCloud {
+        registry_suffix: ".azurecr.us",
+        authority_host: "https://login.microsoftonline.us",
+        resource: "https://management.usgovcloudapi.net/",
+    },
+];
+
+/// The timeout of the establishment of connections to Entra ID and to the
+/// registries
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The timeout of the token requests
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The validity of ACR refresh tokens, when it can't be read out of them
+const DEFAULT_REFRESH_TOKEN_VALIDITY: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Refresh tokens are renewed this long before they expire, to make sure
+/// they don't expire while an image is being pulled
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// An Azure cloud: the public cloud, or one of the national clouds
+struct AzureCloud {
+    registry_suffix: &'static str,
+    authority_host: &'static str,
+    resource: &'static str,
+}
+
+/// Return the cloud of an Azure Container Registry host, or `None` when
+/// `registry` isn't one
+fn cloud_of(registry: &str) -> Option<&'static AzureCloud> {
+    // Ignore the port, if any
+    let host = registry.split(':').next().unwrap_or_default();
+    CLOUDS.iter().find(|cloud| {
+        host.strip_suffix(cloud.registry_suffix)
+            .is_some_and(|name| !name.is_empty() && !name.contains('.'))
+    })
+}
+
+/// Return `true` when `registry` is an Azure Container Registry host, in the
+/// public cloud or in one of the national clouds.
+pub fn is_acr_registry(registry: &str) -> bool {
+    cloud_of(registry).is_some()
+}
+
+/// The Entra ID identity exchanged for ACR refresh tokens
+#[derive(Clone, PartialEq, Eq)]
+pub enum AzureCredential {
+    /// The managed identity of the virtual machine, or of the node, obtained
+    /// through the instance metadata service
+    ManagedIdentity {
+        /// The client ID of the user-assigned identity to use, when the
+        /// virtual machine has several of them
+        client_id: Option<String>,
+    },
+    /// A service principal, authenticated with a client secret
+    ServicePrincipal {
+        /// The ID of the tenant of the service principal
+        tenant_id: String,
+        /// The client ID of the service principal
+        client_id: String,
+        /// The client secret of the service principal
+        client_secret: String,
+    },
+}
+
+impl std::fmt::Debug for AzureCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AzureCredential::ManagedIdentity { client_id } => f
+                .debug_struct("ManagedIdentity")
+                .field("client_id", client_id)
+                .finish(),
+            AzureCredential::ServicePrincipal {
+                tenant_id,
+                client_id,
+                ..
+            } => f
+                .debug_struct("ServicePrincipal")
+                .field("tenant_id", tenant_id)
+                .field("client_id", client_id)
+                .field("client_secret", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+impl AzureCredential {
+    /// Read the credential out of the environment variables used by the
+    /// Azure SDKs: a service principal when `AZURE_TENANT_ID`,
+    /// `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET` are all set, and the
+    /// managed identity selected by `AZURE_CLIENT_ID`, if any, otherwise.
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+        match (
+            var("AZURE_TENANT_ID"),
+            var("AZURE_CLIENT_ID"),
+            var("AZURE_CLIENT_SECRET"),
+        ) {
+            (Some(tenant_id), Some(client_id), Some(client_secret)) => {
+                AzureCredential::ServicePrincipal {
+                    tenant_id,
+                    client_id,
+                    client_secret,
+                }
+            }
+            (_, client_id, _) => AzureCredential::ManagedIdentity { client_id },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct ExchangeResponse {
+    refresh_token: String,
+}
+
+/// Provides the credentials of Azure Container Registries, by exchanging
+/// Entra ID access tokens for registry refresh tokens.
+pub struct AcrAuthProvider {
+    credential: AzureCredential,
+    // Overrides the authority of the cloud of the registry
+    authority_host: Option<String>,
+    http: reqwest::Client,
+    // registry -> (credentials, expiration)
+    cache: Mutex<HashMap<String, (RegistryAuth, SystemTime)>>,
+}
+
+impl AcrAuthProvider {
+    /// Create a provider using the credential configured by the environment.
+    /// See [`AzureCredential::from_env`].
+    pub fn new() -> Self {
+        Self::from_credential(AzureCredential::from_env())
+    }
+
+    /// Create a provider using the given credential.
+    pub fn from_credential(credential: AzureCredential) -> Self {
+        let http = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        AcrAuthProvider {
+            credential,
+            authority_host: None,
+            http,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Use another authority to authenticate service principals than the
+    /// one of the cloud of the registry, such as a private endpoint.
+    pub fn with_authority_host(mut self, authority_host: impl Into<String>) -> Self {
+        self.authority_host = Some(authority_host.into());
+        self
+    }
+
+    /// Request an Entra ID access token for the credential of the provider,
+    /// in the given cloud
+    async fn access_token(&self, cloud: &AzureCloud) -> Result<String> {
+        let request = match &self.credential {
+            AzureCredential::ManagedIdentity { client_id } => {
+                let mut query = vec![("api-version", "2018-02-01"), ("resource", cloud.resource)];
+                if let Some(client_id) = client_id {
+                    query.push(("client_id", client_id));
+                }
+                self.http
+                    .get(IMDS_TOKEN_ENDPOINT)
+                    .header("Metadata", "true")
+                    .query(&query)
+            }
+            AzureCredential::ServicePrincipal {
+                tenant_id,
+                client_id,
+                client_secret,
+            } => {
+                let scope = format!("{}.default", cloud.resource);
+                let authority_host = self
+                    .authority_host
+                    .as_deref()
+                    .unwrap_or(cloud.authority_host);
+                self.http
+                    .post(format!(
+                        "{}/{}/oauth2/v2.0/token",
+                        authority_host.trim_end_matches('/'),
+                        tenant_id
+                    ))
+                    .form(&[
+                        ("grant_type", "client_credentials"),
+                        ("client_id", client_id),
+                        ("client_secret", client_secret),
+                        ("scope", &scope),
+                    ])
+            }
+        };
+
+        debug!(credential = ?self.credential, "Requesting Entra ID access token");
+        let response: AccessTokenResponse = send_json(request)
+            .await
+            .map_err(|e| auth_error("cannot get Entra ID access token", e))?;
+        Ok(response.access_token)
+    }
+}
+
+impl Default for AcrAuthProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RegistryAuthProvider for AcrAuthProvider {
+    /// Return the credentials of `registry`.
+    ///
+    /// Returns `None` when `registry` is not an Azure Container Registry.
+    /// Refresh tokens are cached until shortly before they expire.
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        let Some(cloud) = cloud_of(registry) else {
+            return Ok(None);
+        };
+
+        if let Some((auth, expiration)) = self.cache.lock().unwrap().get(registry) {
+            if SystemTime::now() + EXPIRATION_MARGIN < *expiration {
+                debug!(%registry, "Using cached ACR refresh token");
+                return Ok(Some(auth.clone()));
+            }
+        }
+
+        let access_token = self.access_token(cloud).await?;
+
+        debug!(%registry, "Exchanging Entra ID access token for ACR refresh token");
+        let mut form = vec![
+            ("grant_type", "access_token"),
+            ("service", registry),
+            ("access_token", &access_token),
+        ];
+        if let AzureCredential::ServicePrincipal { tenant_id, .. } = &self.credential {
+            form.push(("tenant", tenant_id));
+        }
+        let request = self
+            .http
+            .post(format!("https://{}/oauth2/exchange", registry))
+            .form(&form);
+        let response: ExchangeResponse = send_json(request)
+            .await
+            .map_err(|e| auth_error("cannot get ACR refresh token", e))?;
+
+        let expiration = refresh_token_expiration(&response.refresh_token);
+        let auth = RegistryAuth::IdentityToken(response.refresh_token);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(registry.to_string(), (auth.clone(), expiration));
+        Ok(Some(auth))
+    }
+}
+
+/// Send a request, and parse its JSON response
+async fn send_json<T: serde::de::DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+) -> std::result::Result<T, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let text = response.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, text));
+    }
+    serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+fn auth_error(context: &str, error: String) -> OciDistributionError {
+    OciDistributionError::AuthenticationFailure(format!("{}: {}", context, error))
+}
+
+/// Return the expiration of an ACR refresh token, which is a JWT
+fn refresh_token_expiration(token: &str) -> SystemTime {
+    jwt::Token::<jwt::header::Header, jwt::claims::Claims, jwt::token::Unverified>::parse_unverified(
+        token,
+    )
+    .ok()
+    .and_then(|token| token.claims().registered.expiration)
+    .map(|exp| UNIX_EPOCH + Duration::from_secs(exp))
+    .unwrap_or_else(|| SystemTime::now() + DEFAULT_REFRESH_TOKEN_VALIDITY)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base64::Engine;
+
+    #[test]
+    fn detect_acr_registries() {
+        assert!(is_acr_registry("myregistry.azurecr.io"));
+        assert!(is_acr_registry("myregistry.azurecr.io:443"));
+        assert!(is_acr_registry("myregistry.azurecr.cn"));
+        assert!(is_acr_registry("myregistry.azurecr.us"));
+        assert!(!is_acr_registry("azurecr.io"));
+        assert!(!is_acr_registry(".azurecr.io"));
+        assert!(!is_acr_registry("evil.com.azurecr.io"));
+        assert!(!is_acr_registry("myregistry.azurecr.io.evil.com"));
+        assert!(!is_acr_registry("ghcr.io"));
+    }
+
+    #[test]
+    fn select_cloud() {
+        let resource = |registry| cloud_of(registry).unwrap().resource;
+        assert_eq!(
+            resource("myregistry.azurecr.io"),
+            "https://management.azure.com/"
+        );
+        assert_eq!(
+            resource("myregistry.azurecr.cn"),
+            "https://management.chinacloudapi.cn/"
+        );
+        assert_eq!(
+            cloud_of("myregistry.azurecr.us").unwrap().authority_host,
+            "https://login.microsoftonline.us"
+        );
+    }
+
+    #[test]
+    fn read_refresh_token_expiration() {
+        let b64url = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let token = format!(
+            "{}.{}.{}",
+            b64url.encode(r#"{"alg":"RS256","typ":"JWT"}"#),
+            b64url.encode(r#"{"exp":1700000000,"iss":"Azure Container Registry"}"#),
+            b64url.encode("signature")
+        );
+        assert_eq!(
+            refresh_token_expiration(&token),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+
+        // Opaque tokens are assumed to have the default validity
+        let expiration = refresh_token_expiration("opaque");
+        let validity = expiration.duration_since(SystemTime::now()).unwrap();
+        assert!(validity > DEFAULT_REFRESH_TOKEN_VALIDITY - Duration::from_secs(60));
+    }
+
+    #[test]
+    fn redact_client_secret() {
+        let credential = AzureCredential::ServicePrincipal {
+            tenant_id: "tenant".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+        };
+        assert!(!format!("{:?}", credential).contains("\"secret\""));
+    }
+
+    #[tokio::test]
+    async fn ignore_other_registries() {
+        let provider =
+            AcrAuthProvider::from_credential(AzureCredential::ManagedIdentity { client_id: None });
+        assert_eq!(provider.credentials("ghcr.io").await.unwrap(), None);
+    }
+}
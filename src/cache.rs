@@ -0,0 +1,656 @@
+//! A persistent, content-addressed cache of blobs
+//!
+//! Images often share layers, and the same image is usually pulled many times
+//! on the same node. An [`ImageCache`] stores the blobs pulled from registries
+//! on disk, addressed by their digest, so that they are only downloaded once:
+//! when [`ClientConfig::image_cache`](crate::client::ClientConfig::image_cache)
+//! is set, [`Client::pull`](crate::Client::pull) only fetches the layers that
+//! are missing from the cache. The chunks of the [eStargz](crate::estargz)
+//! layers that are read lazily are stored the same way, addressed by the
+//! digest of their content.
+//!
+//! The manifests and configs of the pulled images are stored as well, along
+//! with the manifest each reference resolved to and whether its signatures
+//! have been verified. This lets
+//! [`PullPolicy::IfNotPresent`](crate::client::PullPolicy::IfNotPresent) and
+//! [`PullPolicy::Never`](crate::client::PullPolicy::Never) pull images without
+//! reaching the registry.
+//!
+//! The blobs are stored using the same layout as the `blobs` directory of an
+//! [OCI image layout](crate::layout): `<root>/blobs/<algorithm>/<encoded>`.
+//! Blobs are written to a temporary file first, then moved into place, so a
+//! blob found in the cache is always complete. Their digest is verified before
+//! they are stored, and again when they are read, to detect corrupted files.
+//!
+//! The cache keeps track of the last time each blob has been used, and can be
+//! garbage collected with [`ImageCache::garbage_collect`], which evicts the
+//! least recently used blobs once the disk usage goes over a high threshold,
+//! following the semantics of the image garbage collection of the kubelet.
+//!
+//! *Note*: the methods of [`ImageCache`] perform blocking filesystem
+//! operations.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use tracing::{debug, info, warn};
+
+use crate::digest;
+use crate::errors::{OciDistributionError, Result};
+use crate::sha256_digest;
+
+const BLOBS_DIR: &str = "blobs";
+const REFS_DIR: &str = "refs";
+const TMP_DIR: &str = "tmp";
+const VERIFIED_DIR: &str = "verified";
+/// The age after which the temporary files of interrupted writes are removed
+const STALE_TMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// A blob stored inside of an [`ImageCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// The digest of the blob
+    pub digest: String,
+    /// The size of the blob, in bytes
+    pub size: u64,
+    /// The last time the blob has been stored or read
+    pub last_used: SystemTime,
+}
+
+/// When and how much the image garbage collection frees, mirroring the
+/// `imageGCHighThresholdPercent`, `imageGCLowThresholdPercent` and
+/// `imageMinimumGCAge` settings of the kubelet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageGcPolicy {
+    /// The disk usage, in percent, over which blobs are evicted. Defaults
+    /// to 85.
+    pub high_threshold_percent: u8,
+    /// The disk usage, in percent, the garbage collection frees space down
+    /// to. Defaults to 80.
+    pub low_threshold_percent: u8,
+    /// Blobs used more recently than this are never evicted. Defaults to 2
+    /// minutes.
+    pub min_age: Duration,
+}
+
+impl Default for ImageGcPolicy {
+    fn default() -> Self {
+        ImageGcPolicy {
+            high_threshold_percent: 85,
+            low_threshold_percent: 80,
+            min_age: Duration::from_secs(2 * 60),
+        }
+    }
+}
+
+/// The usage of the filesystem holding an [`ImageCache`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// The capacity of the filesystem, in bytes
+    pub capacity: u64,
+    /// The number of bytes in use on the filesystem, including the blobs
+    /// of the cache
+    pub used: u64,
+}
+
+/// The outcome of a garbage collection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// The digests of the evicted blobs
+    pub removed: Vec<String>,
+    /// The number of bytes freed
+    pub freed: u64,
+}
+
+/// An on-disk store of blobs, keyed by digest and shared across images
+#[derive(Debug)]
+pub struct ImageCache {
+    root: PathBuf,
+    // The total size of the blobs, in bytes
+    size: Mutex<u64>,
+    // Used to name temporary files
+    counter: AtomicUsize,
+}
+
+impl ImageCache {
+    /// Open the cache stored in the `root` directory, creating the directory
+    /// if it doesn't exist yet.
+    ///
+    /// Leftovers of interrupted writes are removed, and the size of the blobs
+    /// already stored is computed. The temporary files of writes that may
+    /// still be in progress, in other processes sharing the cache, are kept.
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(root.join(BLOBS_DIR))?;
+        fs::create_dir_all(root.join(REFS_DIR))?;
+        let tmp = root.join(TMP_DIR);
+        fs::create_dir_all(&tmp)?;
+        remove_stale_files(&tmp)?;
+
+        let cache = ImageCache {
+            root,
+            size: Mutex::new(0),
+            counter: AtomicUsize::new(0),
+        };
+        let size = cache.entries()?.iter().map(|e| e.size).sum();
+        *cache.size.lock().unwrap() = size;
+        debug!(root = ?cache.root, size, "Opened image cache");
+        Ok(cache)
+    }
+
+    /// The directory holding the cache
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The total size of the blobs stored inside of the cache, in bytes
+    pub fn size(&self) -> u64 {
+        *self.size.lock().unwrap()
+    }
+
+    /// Return the path of the file holding the blob with the given digest.
+    /// The file might not exist.
+    pub fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        let (algorithm, encoded) = split_digest(digest)?;
+        Ok(self.root.join(BLOBS_DIR).join(algorithm).join(encoded))
+    }
+
+    /// Return `true` when the blob with the given digest is stored inside of
+    /// the cache
+    pub fn contains(&self, digest: &str) -> bool {
+        self.blob_path(digest).is_ok_and(|path| path.is_file())
+    }
+
+    /// Return the content of the blob with the given digest, or `None` when
+    /// it isn't stored inside of the cache.
+    ///
+    /// The content is verified against the digest: a corrupted blob is
+    /// removed from the cache, and `None` is returned.
+    pub fn get(&self, digest: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.blob_path(digest)?;
+        match fs::read(&path) {
+            Ok(data) => {
+                if let Err(e) = digest::verify(&data, digest) {
+                    warn!(%digest, error = %e, "Removing corrupted blob from image cache");
+                    self.remove(digest)?;
+                    return Ok(None);
+                }
+                touch(&path);
+                Ok(Some(data))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a blob inside of the cache.
+    ///
+    /// Fails with [`OciDistributionError::DigestMismatchError`] when `data`
+    /// doesn't match `digest`. Storing a blob that is already cached does
+    /// nothing.
+    pub fn insert(&self, digest: &str, data: &[u8]) -> Result<()> {
+        let path = self.blob_path(digest)?;
+        if path.is_file() {
+            touch(&path);
+            return Ok(());
+        }
+        digest::verify(data, digest)?;
+
+        let tmp = self.tmp_path();
+        fs::write(&tmp, data)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Hold the lock while moving the blob into place, so that concurrent
+        // inserts of the same blob are only accounted for once
+        let mut size = self.size.lock().unwrap();
+        if path.is_file() {
+            fs::remove_file(&tmp)?;
+        } else {
+            fs::rename(&tmp, &path)?;
+            *size += data.len() as u64;
+            debug!(%digest, size = data.len(), "Stored blob in image cache");
+        }
+        Ok(())
+    }
+
+    /// Record that `reference`, such as `docker.io/library/nginx:latest`,
+    /// resolves to the manifest with the given digest. Recording a reference
+    /// again replaces its previous digest.
+    pub fn set_reference(&self, reference: &str, digest: &str) -> Result<()> {
+        split_digest(digest)?;
+        let tmp = self.tmp_path();
+        fs::write(&tmp, digest)?;
+        fs::rename(&tmp, self.reference_path(reference))?;
+        debug!(%reference, %digest, "Stored reference in image cache");
+        Ok(())
+    }
+
+    /// Return the digest of the manifest `reference` resolves to, or `None`
+    /// when the reference hasn't been recorded. The manifest itself might
+    /// have been evicted since.
+    pub fn resolve_reference(&self, reference: &str) -> Result<Option<String>> {
+        match fs::read_to_string(self.reference_path(reference)) {
+            Ok(digest) => Ok(Some(digest)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record that a trusted signature of the manifest with the given digest
+    /// has been verified, so that the image can be pulled from the cache
+    /// without verifying it again
+    pub fn set_verified(&self, digest: &str) -> Result<()> {
+        let path = self.verified_path(digest)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, b"")?;
+        debug!(%digest, "Recorded verified signature in image cache");
+        Ok(())
+    }
+
+    /// Return `true` when a trusted signature of the manifest with the given
+    /// digest has been recorded with [`ImageCache::set_verified`]
+    pub fn is_verified(&self, digest: &str) -> bool {
+        self.verified_path(digest).is_ok_and(|path| path.is_file())
+    }
+
+    fn verified_path(&self, digest: &str) -> Result<PathBuf> {
+        let (algorithm, encoded) = split_digest(digest)?;
+        Ok(self.root.join(VERIFIED_DIR).join(algorithm).join(encoded))
+    }
+
+    // References can contain any character, their digest is used as file name
+    fn reference_path(&self, reference: &str) -> PathBuf {
+        let digest = sha256_digest(reference.as_bytes());
+        let encoded = digest.trim_start_matches("sha256:");
+        self.root.join(REFS_DIR).join(encoded)
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        self.root.join(TMP_DIR).join(format!(
+            "{}-{}",
+            std::process::id(),
+            self.counter.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// Remove a blob from the cache. Returns `false` when the blob wasn't
+    /// stored inside of the cache.
+    pub fn remove(&self, digest: &str) -> Result<bool> {
+        let path = self.blob_path(digest)?;
+        let mut size = self.size.lock().unwrap();
+        let blob_size = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        fs::remove_file(&path)?;
+        if let Err(e) = fs::remove_file(self.verified_path(digest)?) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        *size = size.saturating_sub(blob_size);
+        debug!(%digest, "Removed blob from image cache");
+        Ok(true)
+    }
+
+    /// List the blobs stored inside of the cache
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        for algorithm in fs::read_dir(self.root.join(BLOBS_DIR))? {
+            let algorithm = algorithm?;
+            if !algorithm.file_type()?.is_dir() {
+                continue;
+            }
+            for blob in fs::read_dir(algorithm.path())? {
+                let blob = blob?;
+                let metadata = blob.metadata()?;
+                if !metadata.is_file() {
+                    continue;
+                }
+                entries.push(CacheEntry {
+                    digest: format!(
+                        "{}:{}",
+                        algorithm.file_name().to_string_lossy(),
+                        blob.file_name().to_string_lossy()
+                    ),
+                    size: metadata.len(),
+                    last_used: metadata.modified()?,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Evict the least recently used blobs when the disk usage is over the
+    /// high threshold of `policy`, until it goes under the low threshold.
+    ///
+    /// The blobs listed in `in_use`, such as the layers of the images of
+    /// running workloads, and the blobs used more recently than the minimum
+    /// age of the policy are kept. The garbage collection therefore stops
+    /// short of the low threshold when not enough blobs can be evicted.
+    pub fn garbage_collect(
+        &self,
+        policy: &ImageGcPolicy,
+        usage: DiskUsage,
+        in_use: &HashSet<String>,
+    ) -> Result<GcReport> {
+        if policy.low_threshold_percent >= policy.high_threshold_percent
+            || policy.high_threshold_percent > 100
+        {
+            return Err(OciDistributionError::ImageCacheError(format!(
+                "invalid garbage collection thresholds: low {}%, high {}%",
+                policy.low_threshold_percent, policy.high_threshold_percent
+            )));
+        }
+
+        let mut report = GcReport::default();
+        if usage.capacity == 0
+            || usage.used * 100 < usage.capacity * policy.high_threshold_percent as u64
+        {
+            return Ok(report);
+        }
+        let target = usage.capacity * policy.low_threshold_percent as u64 / 100;
+        let to_free = usage.used.saturating_sub(target);
+        info!(
+            used = usage.used,
+            capacity = usage.capacity,
+            to_free,
+            "Disk usage over the high threshold, evicting cached blobs"
+        );
+
+        let now = SystemTime::now();
+        let mut candidates: Vec<CacheEntry> = self
+            .entries()?
+            .into_iter()
+            .filter(|e| !in_use.contains(&e.digest))
+            .filter(|e| {
+                now.duration_since(e.last_used)
+                    .is_ok_and(|age| age >= policy.min_age)
+            })
+            .collect();
+        candidates.sort_by_key(|e| e.last_used);
+
+        for entry in candidates {
+            if report.freed >= to_free {
+                break;
+            }
+            if self.remove(&entry.digest)? {
+                report.freed += entry.size;
+                report.removed.push(entry.digest);
+            }
+        }
+        if report.freed < to_free {
+            warn!(
+                freed = report.freed,
+                to_free, "Not enough unused blobs to reach the low threshold"
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// Remove the files of `dir` that haven't been modified for
+/// [`STALE_TMP_AGE`]
+fn remove_stale_files(dir: &Path) -> Result<()> {
+    let now = SystemTime::now();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let modified = entry.metadata()?.modified()?;
+        if now.duration_since(modified).unwrap_or_default() > STALE_TMP_AGE {
+            debug!(path = ?entry.path(), "Removing stale temporary file");
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Record that the blob stored at `path` has just been used. Failures are
+/// only logged, since they merely affect which blobs are evicted first.
+fn touch(path: &Path) {
+    let result = fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|f| f.set_modified(SystemTime::now()));
+    if let Err(e) = result {
+        debug!(?path, error = %e, "Cannot update the last use of a blob");
+    }
+}
+
+/// Split a digest into its algorithm and encoded parts, making sure they can
+/// safely be used as file names
+fn split_digest(digest: &str) -> Result<(&str, &str)> {
+    let invalid = || OciDistributionError::ImageCacheError(format!("invalid digest {}", digest));
+    let (algorithm, encoded) = digest.split_once(':').ok_or_else(invalid)?;
+    if algorithm.is_empty()
+        || encoded.is_empty()
+        || !algorithm
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+._-".contains(c))
+        || !encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "=_-".contains(c))
+        || algorithm.starts_with('.')
+    {
+        return Err(invalid());
+    }
+    Ok((algorithm, encoded))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HELLO_DIGEST: &str =
+        "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const WORLD_DIGEST: &str =
+        "sha256:486ea46224d1bb4fb680f34f7c9ad96a8f24ec88be73ea8e5a6c65260e9cb8a7";
+
+    #[test]
+    fn store_and_retrieve_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path()).unwrap();
+
+        assert!(!cache.contains(HELLO_DIGEST));
+        assert_eq!(cache.get(HELLO_DIGEST).unwrap(), None);
+
+        cache.insert(HELLO_DIGEST, b"hello").unwrap();
+        cache.insert(HELLO_DIGEST, b"hello").unwrap();
+        cache.insert(WORLD_DIGEST, b"world").unwrap();
+        assert!(cache.contains(HELLO_DIGEST));
+        assert_eq!(cache.get(HELLO_DIGEST).unwrap().unwrap(), b"hello");
+        assert_eq!(cache.size(), 10);
+        assert!(dir
+            .path()
+            .join("blobs/sha256/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+            .is_file());
+
+        // The size is computed again when the cache is reopened
+        drop(cache);
+        let cache = ImageCache::new(dir.path()).unwrap();
+        assert_eq!(cache.size(), 10);
+        let mut entries: Vec<_> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.digest, e.size))
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(HELLO_DIGEST.to_string(), 5), (WORLD_DIGEST.to_string(), 5)]
+        );
+
+        assert!(cache.remove(HELLO_DIGEST).unwrap());
+        assert!(!cache.remove(HELLO_DIGEST).unwrap());
+        assert!(!cache.contains(HELLO_DIGEST));
+        assert_eq!(cache.size(), 5);
+    }
+
+    #[test]
+    fn store_and_resolve_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path()).unwrap();
+        let reference = "docker.io/library/hello-world:latest";
+
+        assert_eq!(cache.resolve_reference(reference).unwrap(), None);
+        cache.set_reference(reference, HELLO_DIGEST).unwrap();
+        assert_eq!(
+            cache.resolve_reference(reference).unwrap().as_deref(),
+            Some(HELLO_DIGEST)
+        );
+        cache.set_reference(reference, WORLD_DIGEST).unwrap();
+        assert_eq!(
+            cache.resolve_reference(reference).unwrap().as_deref(),
+            Some(WORLD_DIGEST)
+        );
+        // References aren't blobs
+        assert!(cache.entries().unwrap().is_empty());
+
+        assert!(!cache.is_verified(HELLO_DIGEST));
+        cache.set_verified(HELLO_DIGEST).unwrap();
+        assert!(cache.is_verified(HELLO_DIGEST));
+        assert!(cache.entries().unwrap().is_empty());
+
+        assert!(cache.set_reference(reference, "../etc").is_err());
+    }
+
+    #[test]
+    fn reject_invalid_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path()).unwrap();
+
+        assert!(matches!(
+            cache.insert(HELLO_DIGEST, b"goodbye"),
+            Err(OciDistributionError::DigestMismatchError { .. })
+        ));
+        assert!(!cache.contains(HELLO_DIGEST));
+        assert_eq!(cache.size(), 0);
+
+        // Corrupted blobs are removed
+        cache.insert(WORLD_DIGEST, b"world").unwrap();
+        fs::write(cache.blob_path(WORLD_DIGEST).unwrap(), b"w0rld").unwrap();
+        assert_eq!(cache.get(WORLD_DIGEST).unwrap(), None);
+        assert!(!cache.contains(WORLD_DIGEST));
+        assert_eq!(cache.size(), 0);
+
+        for digest in ["sha256", "sha256:../../etc", "../sha256:abc", ":abc"] {
+            assert!(
+                matches!(
+                    cache.blob_path(digest),
+                    Err(OciDistributionError::ImageCacheError(_))
+                ),
+                "{}",
+                digest
+            );
+        }
+    }
+
+    #[test]
+    fn remove_stale_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let tmp = dir.path().join(TMP_DIR);
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(tmp.join("1-0"), b"interrupted").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(tmp.join("1-0"))
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * STALE_TMP_AGE)
+            .unwrap();
+        fs::write(tmp.join("2-0"), b"in progress").unwrap();
+
+        ImageCache::new(dir.path()).unwrap();
+        assert!(!tmp.join("1-0").exists());
+        assert!(tmp.join("2-0").exists());
+    }
+
+    fn set_last_used(cache: &ImageCache, digest: &str, last_used: SystemTime) {
+        fs::File::options()
+            .write(true)
+            .open(cache.blob_path(digest).unwrap())
+            .unwrap()
+            .set_modified(last_used)
+            .unwrap();
+    }
+
+    #[test]
+    fn garbage_collect_least_recently_used() {
+        const FOO_DIGEST: &str =
+            "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path()).unwrap();
+        let policy = ImageGcPolicy::default();
+        cache.insert(HELLO_DIGEST, b"hello").unwrap();
+        cache.insert(WORLD_DIGEST, b"world").unwrap();
+        cache.insert(FOO_DIGEST, b"foo").unwrap();
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        set_last_used(&cache, HELLO_DIGEST, hour_ago - Duration::from_secs(60));
+        set_last_used(&cache, WORLD_DIGEST, hour_ago);
+
+        // Under the high threshold
+        let usage = DiskUsage {
+            capacity: 100,
+            used: 84,
+        };
+        assert_eq!(
+            cache
+                .garbage_collect(&policy, usage, &HashSet::new())
+                .unwrap(),
+            GcReport::default()
+        );
+
+        // Over the high threshold: the least recently used blob is enough to
+        // reach the low threshold
+        let usage = DiskUsage {
+            capacity: 100,
+            used: 85,
+        };
+        let report = cache
+            .garbage_collect(&policy, usage, &HashSet::new())
+            .unwrap();
+        assert_eq!(report.removed, vec![HELLO_DIGEST.to_string()]);
+        assert_eq!(report.freed, 5);
+
+        // Blobs in use and recently used blobs are kept
+        cache.insert(HELLO_DIGEST, b"hello").unwrap();
+        set_last_used(&cache, HELLO_DIGEST, hour_ago);
+        let in_use = [WORLD_DIGEST.to_string()].into_iter().collect();
+        let usage = DiskUsage {
+            capacity: 100,
+            used: 100,
+        };
+        let report = cache.garbage_collect(&policy, usage, &in_use).unwrap();
+        assert_eq!(report.removed, vec![HELLO_DIGEST.to_string()]);
+        assert!(cache.contains(WORLD_DIGEST));
+        assert!(cache.contains(FOO_DIGEST));
+        assert_eq!(cache.size(), 8);
+    }
+
+    #[test]
+    fn reject_invalid_gc_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path()).unwrap();
+        let policy = ImageGcPolicy {
+            high_threshold_percent: 80,
+            low_threshold_percent: 85,
+            ..Default::default()
+        };
+        let usage = DiskUsage {
+            capacity: 100,
+            used: 90,
+        };
+        assert!(cache
+            .garbage_collect(&policy, usage, &HashSet::new())
+            .is_err());
+    }
+}
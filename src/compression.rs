@@ -0,0 +1,163 @@
+//! Decompression of image layers
+//!
+//! Layers are usually compressed: with gzip, or with zstd by the newer
+//! versions of buildkit and containerd. The compression is described by the
+//! suffix of the media type of the layer, such as `+gzip` or `+zstd`. This
+//! module picks the right decoder out of the media type, which is what
+//! [`Client::pull_layer_decompressed`](crate::Client::pull_layer_decompressed)
+//! and [`ImageLayer::decompress`](crate::client::ImageLayer::decompress) use.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_compression::tokio::write::{GzipDecoder, ZstdDecoder};
+use tokio::io::AsyncWrite;
+
+/// The compression of a layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// The layer isn't compressed
+    None,
+    /// The layer is compressed with gzip
+    Gzip,
+    /// The layer is compressed with zstd
+    Zstd,
+}
+
+impl Compression {
+    /// Return the compression described by the media type of a layer.
+    ///
+    /// Media types without a compression suffix, such as
+    /// `application/vnd.oci.image.layer.v1.tar` or the media types of
+    /// WebAssembly modules, are considered uncompressed.
+    pub fn from_media_type(media_type: &str) -> Self {
+        if media_type.ends_with("+gzip") || media_type.ends_with(".tar.gzip") {
+            Compression::Gzip
+        } else if media_type.ends_with("+zstd") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// An `AsyncWrite` decompressing the data written to it into the inner
+/// writer. It must be shut down once all the data has been written, which
+/// makes sure the compressed data was complete.
+pub(crate) enum Decompressor<W> {
+    None(W),
+    Gzip(GzipDecoder<W>),
+    Zstd(ZstdDecoder<W>),
+}
+
+impl<W: AsyncWrite + Unpin> Decompressor<W> {
+    pub(crate) fn new(compression: Compression, out: W) -> Self {
+        match compression {
+            Compression::None => Decompressor::None(out),
+            Compression::Gzip => Decompressor::Gzip(GzipDecoder::new(out)),
+            Compression::Zstd => Decompressor::Zstd(ZstdDecoder::new(out)),
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Decompressor<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Decompressor::None(w) => Pin::new(w).poll_write(cx, buf),
+            Decompressor::Gzip(w) => Pin::new(w).poll_write(cx, buf),
+            Decompressor::Zstd(w) => Pin::new(w).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Decompressor::None(w) => Pin::new(w).poll_flush(cx),
+            Decompressor::Gzip(w) => Pin::new(w).poll_flush(cx),
+            Decompressor::Zstd(w) => Pin::new(w).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Decompressor::None(w) => Pin::new(w).poll_shutdown(cx),
+            Decompressor::Gzip(w) => Pin::new(w).poll_shutdown(cx),
+            Decompressor::Zstd(w) => Pin::new(w).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::{
+        IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE, IMAGE_LAYER_GZIP_MEDIA_TYPE, IMAGE_LAYER_MEDIA_TYPE,
+        IMAGE_LAYER_NONDISTRIBUTABLE_ZSTD_MEDIA_TYPE, IMAGE_LAYER_ZSTD_MEDIA_TYPE,
+        WASM_LAYER_MEDIA_TYPE,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn compression_from_media_type() {
+        assert_eq!(
+            Compression::from_media_type(IMAGE_LAYER_MEDIA_TYPE),
+            Compression::None
+        );
+        assert_eq!(
+            Compression::from_media_type(WASM_LAYER_MEDIA_TYPE),
+            Compression::None
+        );
+        assert_eq!(
+            Compression::from_media_type(IMAGE_LAYER_GZIP_MEDIA_TYPE),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_media_type(IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_media_type(IMAGE_LAYER_ZSTD_MEDIA_TYPE),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::from_media_type(IMAGE_LAYER_NONDISTRIBUTABLE_ZSTD_MEDIA_TYPE),
+            Compression::Zstd
+        );
+    }
+
+    #[tokio::test]
+    async fn decompress() {
+        // "hello" compressed with `gzip -n` and `zstd`
+        let gzip: &[u8] = &[
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0x07, 0x00, 0x86, 0xa6, 0x10, 0x36, 0x05, 0x00, 0x00, 0x00,
+        ];
+        let zstd: &[u8] = &[
+            0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x58, 0x29, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+            0xa3, 0x6d, 0x9f, 0x88,
+        ];
+
+        for (compression, data) in [
+            (Compression::None, b"hello".as_slice()),
+            (Compression::Gzip, gzip),
+            (Compression::Zstd, zstd),
+        ] {
+            let mut out = Vec::new();
+            let mut decompressor = Decompressor::new(compression, &mut out);
+            // Feed the data in several chunks
+            for chunk in data.chunks(4) {
+                decompressor.write_all(chunk).await.unwrap();
+            }
+            decompressor.shutdown().await.unwrap();
+            assert_eq!(out, b"hello", "{:?}", compression);
+        }
+
+        let mut out = Vec::new();
+        let mut decompressor = Decompressor::new(Compression::Zstd, &mut out);
+        decompressor.write_all(&zstd[..10]).await.unwrap();
+        assert!(decompressor.shutdown().await.is_err());
+    }
+}
@@ -309,7 +309,7 @@ pub struct History {
 #[cfg(test)]
 mod tests {
     use assert_json_diff::assert_json_eq;
-    use chrono::{TimeZone, Utc};
+    use chrono::{DateTime, Utc};
     use rstest::*;
     use serde_json::Value;
     use std::collections::{HashMap, HashSet};
@@ -411,14 +411,14 @@ mod tests {
         };
 
         let history = Some(vec![History {
-            created: Some(Utc.datetime_from_str("2015-10-31T22:22:54.690851953Z", "%+").expect("parse time failed")),
+            created: Some("2015-10-31T22:22:54.690851953Z".parse::<DateTime<Utc>>().expect("parse time failed")),
             author: None,
             created_by: Some("/bin/sh -c #(nop) ADD file:a3bc1e842b69636f9df5256c49c5374fb4eef1e281fe3f282c65fb853ee171c5 in /".into()),
             comment: None,
             empty_layer: None,
         },
         History {
-            created: Some(Utc.datetime_from_str("2015-10-31T22:22:55.613815829Z", "%+").expect("parse time failed")),
+            created: Some("2015-10-31T22:22:55.613815829Z".parse::<DateTime<Utc>>().expect("parse time failed")),
             author: None,
             created_by: Some("/bin/sh -c #(nop) CMD [\"sh\"]".into()),
             comment: None,
@@ -426,7 +426,8 @@ mod tests {
         }]);
         ConfigFile {
             created: Some(
-                Utc.datetime_from_str("2015-10-31T22:22:56.015925234Z", "%+")
+                "2015-10-31T22:22:56.015925234Z"
+                    .parse::<DateTime<Utc>>()
                     .expect("parse time failed"),
             ),
             author: Some("Alyssa P. Hacker <alyspdev@example.com>".into()),
@@ -503,7 +504,8 @@ mod tests {
         });
         let history = Some(vec![History {
             created: Some(
-                Utc.datetime_from_str("2023-04-21T11:53:28.176613804Z", "%+")
+                "2023-04-21T11:53:28.176613804Z"
+                    .parse::<DateTime<Utc>>()
                     .expect("parse time failed"),
             ),
             author: None,
@@ -526,7 +528,8 @@ mod tests {
             rootfs,
             history,
             created: Some(
-                Utc.datetime_from_str("2023-04-21T11:53:28.176613804Z", "%+")
+                "2023-04-21T11:53:28.176613804Z"
+                    .parse::<DateTime<Utc>>()
                     .expect("parse time failed"),
             ),
             author: None,
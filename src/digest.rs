@@ -0,0 +1,190 @@
+//! Incremental verification of content digests
+//!
+//! Content served by a registry is addressed by its digest. This module allows
+//! to compute the digest of a blob while it is being downloaded, and to reject
+//! the blob when it doesn't match the digest that was requested.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::Stream;
+use sha2::Digest;
+
+use crate::errors::{OciDistributionError, Result};
+
+/// Computes the digest of some content, using the algorithm of the digest
+/// the content is expected to match
+pub(crate) enum Digester {
+    Sha256(sha2::Sha256),
+    Sha384(sha2::Sha384),
+    Sha512(sha2::Sha512),
+}
+
+impl Digester {
+    /// Create a `Digester` for the algorithm used by `digest`, which must be
+    /// in the `<algorithm>:<encoded>` form
+    pub(crate) fn new(digest: &str) -> Result<Self> {
+        let (algorithm, _) = digest.split_once(':').ok_or_else(|| {
+            OciDistributionError::UnsupportedDigestAlgorithmError(digest.to_string())
+        })?;
+        match algorithm {
+            "sha256" => Ok(Digester::Sha256(sha2::Sha256::new())),
+            "sha384" => Ok(Digester::Sha384(sha2::Sha384::new())),
+            "sha512" => Ok(Digester::Sha512(sha2::Sha512::new())),
+            other => Err(OciDistributionError::UnsupportedDigestAlgorithmError(
+                other.to_string(),
+            )),
+        }
+    }
+
+    /// Feed some more content to the digester
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Digester::Sha256(d) => d.update(data),
+            Digester::Sha384(d) => d.update(data),
+            Digester::Sha512(d) => d.update(data),
+        }
+    }
+
+    /// Return the digest of the content, in the `<algorithm>:<encoded>` form
+    pub(crate) fn finalize(self) -> String {
+        match self {
+            Digester::Sha256(d) => format!("sha256:{:x}", d.finalize()),
+            Digester::Sha384(d) => format!("sha384:{:x}", d.finalize()),
+            Digester::Sha512(d) => format!("sha512:{:x}", d.finalize()),
+        }
+    }
+
+    /// Ensure the digest of the content matches the `expected` one
+    pub(crate) fn verify(self, expected: &str) -> Result<()> {
+        let actual = self.finalize();
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(OciDistributionError::DigestMismatchError {
+                expected: expected.to_string(),
+                actual,
+            })
+        }
+    }
+}
+
+/// Ensure the digest of `data` matches the `expected` one
+pub(crate) fn verify(data: &[u8], expected: &str) -> Result<()> {
+    let mut digester = Digester::new(expected)?;
+    digester.update(data);
+    digester.verify(expected)
+}
+
+/// A stream of bytes that computes the digest of the data flowing through it,
+/// and fails with an `InvalidData` error once it is exhausted if the digest
+/// doesn't match the expected one
+pub(crate) struct VerifyingStream<S> {
+    inner: S,
+    digester: Option<Digester>,
+    expected: String,
+}
+
+impl<S> VerifyingStream<S> {
+    pub(crate) fn new(inner: S, expected: &str) -> Result<Self> {
+        Ok(VerifyingStream {
+            inner,
+            digester: Some(Digester::new(expected)?),
+            expected: expected.to_string(),
+        })
+    }
+}
+
+impl<S> Stream for VerifyingStream<S>
+where
+    S: Stream<Item = std::io::Result<bytes::Bytes>> + Unpin,
+{
+    type Item = std::io::Result<bytes::Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(bytes))) => {
+                if let Some(digester) = this.digester.as_mut() {
+                    digester.update(&bytes);
+                }
+                Poll::Ready(Some(Ok(bytes)))
+            }
+            Poll::Ready(None) => match this.digester.take() {
+                Some(digester) => match digester.verify(&this.expected) {
+                    Ok(()) => Poll::Ready(None),
+                    Err(e) => Poll::Ready(Some(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        e,
+                    )))),
+                },
+                None => Poll::Ready(None),
+            },
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::stream::{self, TryStreamExt};
+
+    const HELLO_DIGEST: &str =
+        "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn verify_matching_digest() {
+        verify(b"hello", HELLO_DIGEST).expect("digest should match");
+        verify(
+            b"hello",
+            "sha512:9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca72323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043",
+        )
+        .expect("digest should match");
+    }
+
+    #[test]
+    fn verify_mismatching_digest() {
+        match verify(b"goodbye", HELLO_DIGEST) {
+            Err(OciDistributionError::DigestMismatchError { expected, .. }) => {
+                assert_eq!(expected, HELLO_DIGEST)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verify_unsupported_algorithm() {
+        assert!(matches!(
+            verify(b"hello", "md5:5d41402abc4b2a76b9719d911017c592"),
+            Err(OciDistributionError::UnsupportedDigestAlgorithmError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn verifying_stream() {
+        let chunks = || {
+            stream::iter(vec![
+                Ok(bytes::Bytes::from_static(b"hel")),
+                Ok(bytes::Bytes::from_static(b"lo")),
+            ])
+        };
+
+        let data: Vec<bytes::Bytes> = VerifyingStream::new(chunks(), HELLO_DIGEST)
+            .unwrap()
+            .try_collect()
+            .await
+            .expect("digest should match");
+        assert_eq!(data.concat(), b"hello");
+
+        let err = VerifyingStream::new(
+            chunks(),
+            "sha256:0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .expect_err("digest should not match");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
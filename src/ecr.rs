@@ -0,0 +1,231 @@
+//! Authentication against [Amazon ECR](https://aws.amazon.com/ecr/) registries
+//!
+//! ECR doesn't accept static credentials: an IAM identity has to be exchanged
+//! for a registry password, which is valid for 12 hours. [`EcrAuthProvider`]
+//! performs this exchange automatically for the `*.dkr.ecr.*.amazonaws.com`
+//! registries, and caches the password until it expires.
+//!
+//! The IAM credentials are resolved using the default AWS credentials chain:
+//! environment variables, shared configuration and credentials files, web
+//! identity tokens (as used by IAM roles for service accounts), ECS container
+//! credentials and EC2 instance profiles.
+//!
+//! [`EcrAuthProvider`] implements [`RegistryAuthProvider`], and can be added
+//! to a [`ChainedAuthProvider`](crate::secrets::ChainedAuthProvider) ahead of
+//! the docker configuration.
+//!
+//! This module is available when the `ecr` feature is enabled.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use base64::Engine;
+use tracing::debug;
+
+use crate::errors::{OciDistributionError, Result};
+use crate::secrets::{RegistryAuth, RegistryAuthProvider};
+
+/// The username of the credentials of ECR registries
+const ECR_USERNAME: &str = "AWS";
+
+/// Passwords are refreshed this long before they expire, to make sure they
+/// don't expire while an image is being pulled
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// The registry host of an ECR repository, split into its components
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcrRegistry {
+    /// The ID of the AWS account owning the registry
+    pub account_id: String,
+    /// The AWS region the registry lives in
+    pub region: String,
+}
+
+impl EcrRegistry {
+    /// Parse a registry host of the form
+    /// `<account>.dkr.ecr[-fips].<region>.amazonaws.com[.cn]`.
+    ///
+    /// Returns `None` when the registry is not an ECR registry.
+    pub fn parse(registry: &str) -> Option<Self> {
+        // Ignore the port, if any
+        let host = registry.split(':').next()?;
+        let host = host
+            .strip_suffix(".amazonaws.com")
+            .or_else(|| host.strip_suffix(".amazonaws.com.cn"))?;
+
+        let mut parts = host.splitn(4, '.');
+        let account_id = parts.next()?;
+        let dkr = parts.next()?;
+        let ecr = parts.next()?;
+        let region = parts.next()?;
+
+        if account_id.len() != 12
+            || !account_id.chars().all(|c| c.is_ascii_digit())
+            || dkr != "dkr"
+            || (ecr != "ecr" && ecr != "ecr-fips")
+            || region.is_empty()
+            || region.contains('.')
+        {
+            return None;
+        }
+
+        Some(EcrRegistry {
+            account_id: account_id.to_string(),
+            region: region.to_string(),
+        })
+    }
+}
+
+/// Provides the credentials of Amazon ECR registries, by exchanging IAM
+/// credentials for registry passwords.
+pub struct EcrAuthProvider {
+    sdk_config: aws_config::SdkConfig,
+    // registry -> (credentials, expiration)
+    cache: Mutex<HashMap<String, (RegistryAuth, SystemTime)>>,
+}
+
+impl EcrAuthProvider {
+    /// Create a provider that resolves IAM credentials using the default
+    /// AWS credentials chain.
+    pub async fn new() -> Self {
+        let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Self::from_sdk_config(sdk_config)
+    }
+
+    /// Create a provider using an already loaded AWS configuration.
+    pub fn from_sdk_config(sdk_config: aws_config::SdkConfig) -> Self {
+        EcrAuthProvider {
+            sdk_config,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl RegistryAuthProvider for EcrAuthProvider {
+    /// Return the credentials of `registry`.
+    ///
+    /// Returns `None` when `registry` is not an ECR registry. Passwords are
+    /// cached until shortly before they expire.
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        let ecr_registry = match EcrRegistry::parse(registry) {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+
+        if let Some((auth, expiration)) = self.cache.lock().unwrap().get(registry) {
+            if SystemTime::now() + EXPIRATION_MARGIN < *expiration {
+                debug!(%registry, "Using cached ECR credentials");
+                return Ok(Some(auth.clone()));
+            }
+        }
+
+        debug!(%registry, region = %ecr_registry.region, "Requesting ECR authorization token");
+        let config = aws_sdk_ecr::config::Builder::from(&self.sdk_config)
+            .region(aws_sdk_ecr::config::Region::new(ecr_registry.region))
+            .build();
+        let response = aws_sdk_ecr::Client::from_conf(config)
+            .get_authorization_token()
+            .send()
+            .await
+            .map_err(|e| {
+                OciDistributionError::AuthenticationFailure(format!(
+                    "cannot get ECR authorization token: {}",
+                    aws_sdk_ecr::error::DisplayErrorContext(e)
+                ))
+            })?;
+
+        let data = response.authorization_data().first().ok_or_else(|| {
+            OciDistributionError::AuthenticationFailure(
+                "ECR returned no authorization data".to_string(),
+            )
+        })?;
+        let token = data.authorization_token().ok_or_else(|| {
+            OciDistributionError::AuthenticationFailure(
+                "ECR returned no authorization token".to_string(),
+            )
+        })?;
+        let auth = decode_authorization_token(token)?;
+        let expiration = data
+            .expires_at()
+            .and_then(|e| SystemTime::try_from(*e).ok())
+            .unwrap_or_else(SystemTime::now);
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(registry.to_string(), (auth.clone(), expiration));
+        Ok(Some(auth))
+    }
+}
+
+/// Decode an ECR authorization token, which is the base64 encoding of
+/// `AWS:<password>`
+fn decode_authorization_token(token: &str) -> Result<RegistryAuth> {
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(token)
+        .ok()
+        .and_then(|d| String::from_utf8(d).ok())
+        .ok_or_else(|| {
+            OciDistributionError::AuthenticationFailure(
+                "cannot decode ECR authorization token".to_string(),
+            )
+        })?;
+    match decoded.split_once(':') {
+        Some((ECR_USERNAME, password)) => Ok(RegistryAuth::Basic(
+            ECR_USERNAME.to_string(),
+            password.to_string(),
+        )),
+        _ => Err(OciDistributionError::AuthenticationFailure(
+            "unexpected ECR authorization token format".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_ecr_registry() {
+        assert_eq!(
+            EcrRegistry::parse("123456789012.dkr.ecr.eu-west-1.amazonaws.com"),
+            Some(EcrRegistry {
+                account_id: "123456789012".to_string(),
+                region: "eu-west-1".to_string(),
+            })
+        );
+        assert_eq!(
+            EcrRegistry::parse("123456789012.dkr.ecr-fips.us-east-1.amazonaws.com:443"),
+            Some(EcrRegistry {
+                account_id: "123456789012".to_string(),
+                region: "us-east-1".to_string(),
+            })
+        );
+        assert_eq!(
+            EcrRegistry::parse("123456789012.dkr.ecr.cn-north-1.amazonaws.com.cn"),
+            Some(EcrRegistry {
+                account_id: "123456789012".to_string(),
+                region: "cn-north-1".to_string(),
+            })
+        );
+        assert_eq!(EcrRegistry::parse("public.ecr.aws"), None);
+        assert_eq!(EcrRegistry::parse("ghcr.io"), None);
+        assert_eq!(
+            EcrRegistry::parse("1234.dkr.ecr.eu-west-1.amazonaws.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn decode_token() {
+        // AWS:secret
+        assert_eq!(
+            decode_authorization_token("QVdTOnNlY3JldA==").unwrap(),
+            RegistryAuth::Basic("AWS".to_string(), "secret".to_string())
+        );
+        assert!(decode_authorization_token("not base64!").is_err());
+    }
+}
@@ -8,6 +8,21 @@ pub enum OciDistributionError {
     /// Authentication error
     #[error("Authentication failure: {0}")]
     AuthenticationFailure(String),
+    /// The digest of the content returned by the registry doesn't match the
+    /// digest that was requested
+    #[error("Digest mismatch: expected {expected}, got {actual}")]
+    DigestMismatchError {
+        /// The digest that was requested
+        expected: String,
+        /// The digest of the content that was received
+        actual: String,
+    },
+    /// Transparent wrapper around `docker_credential::CredentialRetrievalError`
+    #[error(transparent)]
+    DockerCredentialError(#[from] docker_credential::CredentialRetrievalError),
+    /// The eStargz layer is invalid, or doesn't contain the requested file
+    #[error("eStargz error: {0}")]
+    EStargzError(String),
     /// Generic error, might provide an explanation message
     #[error("Generic error: {0:?}")]
     GenericError(Option<String>),
@@ -17,12 +32,25 @@ pub enum OciDistributionError {
     /// IO Error
     #[error(transparent)]
     IoError(#[from] std::io::Error),
+    /// The image cache is invalid, or cannot store the requested blob
+    #[error("Image cache error: {0}")]
+    ImageCacheError(String),
     /// Platform resolver not specified
     #[error("Received Image Index/Manifest List, but platform_resolver was not defined on the client config. Consider setting platform_resolver")]
     ImageIndexParsingNoPlatformResolverError,
+    /// The OCI image layout is invalid, or doesn't contain the requested image
+    #[error("Image layout error: {0}")]
+    ImageLayoutError(String),
     /// Image manifest not found
     #[error("Image manifest not found: {0}")]
     ImageManifestNotFoundError(String),
+    /// The image isn't stored inside of the image cache, and the pull policy
+    /// forbids pulling it from the registry
+    #[error("Image not present in the image cache: {0}")]
+    ImageNotCachedError(String),
+    /// Registry returned a config, or an artifact, with an incompatible type
+    #[error("Incompatible config media type: {0}")]
+    IncompatibleConfigMediaTypeError(String),
     /// Registry returned a layer with an incompatible type
     #[error("Incompatible layer media type: {0}")]
     IncompatibleLayerMediaTypeError(String),
@@ -41,6 +69,17 @@ pub enum OciDistributionError {
     /// No layers available to be pulled
     #[error("No layers to pull")]
     PullNoLayersError,
+    /// The registry rejected the request because the rate limit of the client
+    /// has been reached, and it couldn't be retried
+    #[error("Rate limited: url {url}, retry after: {retry_after:?}")]
+    RateLimitedError {
+        /// Request URL
+        url: String,
+        /// How long to wait before retrying, as requested by the registry
+        retry_after: Option<std::time::Duration>,
+        /// The rate limit advertised by the registry
+        rate_limit: Option<crate::retry::RateLimit>,
+    },
     /// OCI registry error
     #[error("Registry error: url {url}, envelope: {envelope}")]
     RegistryError {
@@ -64,7 +103,7 @@ pub enum OciDistributionError {
     /// Cannot parse URL
     #[error("Error parsing Url {0}")]
     UrlParseError(String),
-    /// HTTP Server error
+    /// HTTP Server error, or client error whose body doesn't hold OCI errors
     #[error("Server error: url {url}, code: {code}, message: {message}")]
     ServerError {
         /// HTTP status code
@@ -74,6 +113,9 @@ pub enum OciDistributionError {
         /// Error message returned by the remote server
         message: String,
     },
+    /// The signature of an image could not be verified
+    #[error("Signature verification failed: {0}")]
+    SignatureVerificationError(String),
     /// The [OCI distribution spec](https://github.com/opencontainers/distribution-spec/blob/main/spec.md)
     /// is not respected by the remote registry
     #[error("OCI distribution spec violation: {0}")]
@@ -84,6 +126,9 @@ pub enum OciDistributionError {
         /// request URL
         url: String,
     },
+    /// Digest algorithm not supported
+    #[error("Unsupported digest algorithm: {0}")]
+    UnsupportedDigestAlgorithmError(String),
     /// Media type not supported
     #[error("Unsupported media type: {0}")]
     UnsupportedMediaTypeError(String),
@@ -98,6 +143,82 @@ pub enum OciDistributionError {
     ConfigConversionError(String),
 }
 
+/// The category of an [`OciDistributionError`]
+///
+/// Callers reporting pull failures, such as a kubelet telling authentication
+/// errors apart from missing images, can rely on it instead of matching every
+/// variant of the error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The credentials are missing, invalid, or don't grant access to the
+    /// repository
+    Auth,
+    /// The repository doesn't exist (`NAME_UNKNOWN`)
+    NameUnknown,
+    /// The manifest, or the tag, doesn't exist in the repository
+    /// (`MANIFEST_UNKNOWN`)
+    ManifestUnknown,
+    /// Another resource, such as a blob, doesn't exist
+    NotFound,
+    /// The registry rejected the request because of its rate limit
+    RateLimited,
+    /// The content returned by the registry doesn't match its digest
+    DigestMismatch,
+    /// The registry couldn't be reached, or the connection failed or timed
+    /// out
+    Network,
+    /// Any other error
+    Other,
+}
+
+impl OciDistributionError {
+    /// Return the category of this error.
+    ///
+    /// The errors returned by the registry are classified according to the
+    /// code of the first error of their body, or to their HTTP status when
+    /// they have none.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            OciDistributionError::AuthenticationFailure(_)
+            | OciDistributionError::UnauthorizedError { .. } => ErrorKind::Auth,
+            OciDistributionError::DigestMismatchError { .. } => ErrorKind::DigestMismatch,
+            OciDistributionError::ImageManifestNotFoundError(_) => ErrorKind::ManifestUnknown,
+            OciDistributionError::RateLimitedError { .. } => ErrorKind::RateLimited,
+            OciDistributionError::RegistryError { envelope, .. } => match envelope.errors.first() {
+                Some(error) => error.code.kind(),
+                None => ErrorKind::Other,
+            },
+            OciDistributionError::RequestError(e) => match e.status() {
+                Some(status) => status_kind(status.as_u16()),
+                None if is_network_error(e) => ErrorKind::Network,
+                None => ErrorKind::Other,
+            },
+            OciDistributionError::ServerError { code, .. } => status_kind(*code),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Return `true` when a request failed because the registry couldn't be
+/// reached, rather than because of an invalid request or response
+fn is_network_error(e: &reqwest::Error) -> bool {
+    #[cfg(not(target_arch = "wasm32"))]
+    if e.is_connect() {
+        return true;
+    }
+    e.is_timeout() || e.is_request()
+}
+
+/// Return the category of the errors with the given HTTP status
+fn status_kind(status: u16) -> ErrorKind {
+    match status {
+        401 | 403 => ErrorKind::Auth,
+        404 => ErrorKind::NotFound,
+        429 => ErrorKind::RateLimited,
+        _ => ErrorKind::Other,
+    }
+}
+
 /// Helper type to declare `Result` objects that might return a `OciDistributionError`
 pub type Result<T> = std::result::Result<T, OciDistributionError>;
 
@@ -199,6 +320,20 @@ pub enum OciErrorCode {
     Toomanyrequests,
 }
 
+impl OciErrorCode {
+    /// Return the category of the errors with this code
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            OciErrorCode::Unauthorized | OciErrorCode::Denied => ErrorKind::Auth,
+            OciErrorCode::NameUnknown => ErrorKind::NameUnknown,
+            OciErrorCode::ManifestUnknown => ErrorKind::ManifestUnknown,
+            OciErrorCode::BlobUnknown | OciErrorCode::ManifestBlobUnknown => ErrorKind::NotFound,
+            OciErrorCode::Toomanyrequests => ErrorKind::RateLimited,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -254,4 +389,68 @@ mod test {
         assert_eq!("authentication required", e.message);
         assert_eq!(serde_json::value::Value::Null, e.detail);
     }
+
+    #[test]
+    fn test_error_kind() {
+        let registry_error = |body: &str| OciDistributionError::RegistryError {
+            envelope: serde_json::from_str(body).unwrap(),
+            url: String::new(),
+        };
+        assert_eq!(registry_error(EXAMPLE_ERROR).kind(), ErrorKind::Auth);
+        assert_eq!(
+            registry_error(EXAMPLE_ERROR_TOOMANYREQUESTS).kind(),
+            ErrorKind::RateLimited
+        );
+        assert_eq!(
+            registry_error(r#"{"errors":[{"code":"NAME_UNKNOWN"}]}"#).kind(),
+            ErrorKind::NameUnknown
+        );
+        assert_eq!(
+            registry_error(r#"{"errors":[{"code":"MANIFEST_UNKNOWN"}]}"#).kind(),
+            ErrorKind::ManifestUnknown
+        );
+        assert_eq!(registry_error(r#"{"errors":[]}"#).kind(), ErrorKind::Other);
+
+        let server_error = |code| OciDistributionError::ServerError {
+            code,
+            url: String::new(),
+            message: String::new(),
+        };
+        assert_eq!(server_error(403).kind(), ErrorKind::Auth);
+        assert_eq!(server_error(404).kind(), ErrorKind::NotFound);
+        assert_eq!(server_error(500).kind(), ErrorKind::Other);
+        assert_eq!(
+            OciDistributionError::DigestMismatchError {
+                expected: String::new(),
+                actual: String::new(),
+            }
+            .kind(),
+            ErrorKind::DigestMismatch
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_error_kind() {
+        // Nothing listens on the port
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = reqwest::Client::new();
+        let error = client
+            .get(format!("http://{}/v2/", addr))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            OciDistributionError::RequestError(error).kind(),
+            ErrorKind::Network
+        );
+
+        let error = client.get("not a url").send().await.unwrap_err();
+        assert_eq!(
+            OciDistributionError::RequestError(error).kind(),
+            ErrorKind::Other
+        );
+    }
 }
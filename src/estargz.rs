@@ -0,0 +1,468 @@
+//! Lazy pulling of eStargz layers
+//!
+//! [eStargz](https://github.com/containerd/stargz-snapshotter/blob/main/docs/estargz.md)
+//! layers are regular gzip-compressed tar layers, which are made of many gzip
+//! members: every file, and every chunk of the big files, starts a new member.
+//! A table of contents (TOC), stored at the end of the layer, lists the files
+//! along with the offset of their chunks in the compressed layer. This allows
+//! fetching the content of single files with range requests, without
+//! downloading the whole layer, so that containers can start before their
+//! image has been fully pulled.
+//!
+//! [`Client::pull_lazy`](crate::Client::pull_lazy) only fetches the TOC of the
+//! eStargz layers of an image, and their files are then read on demand with
+//! [`Client::read_estargz_file`](crate::Client::read_estargz_file). When
+//! [`ClientConfig::image_cache`](crate::client::ClientConfig::image_cache) is
+//! set, the fetched chunks are stored in the image cache, addressed by their
+//! digest, so they are only fetched once.
+//!
+//! Since eStargz layers are valid gzip layers, they can also be pulled the
+//! usual way.
+
+use std::ops::Range;
+
+use async_compression::tokio::bufread::GzipDecoder;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+use crate::errors::{OciDistributionError, Result};
+use crate::manifest::OciDescriptor;
+
+/// The annotation holding the digest of the TOC of an eStargz layer
+pub const TOC_DIGEST_ANNOTATION: &str = "containerd.io/snapshot/stargz/toc.digest";
+/// The annotation holding the size of an eStargz layer once decompressed
+pub const UNCOMPRESSED_SIZE_ANNOTATION: &str = "io.containers.estargz.uncompressed-size";
+/// The name of the tar entry holding the TOC
+pub const TOC_FILE_NAME: &str = "stargz.index.json";
+
+/// The size of the footer of an eStargz layer, which holds the offset of the
+/// TOC: an empty gzip member with the offset in its extra field
+pub(crate) const FOOTER_SIZE: u64 = 51;
+
+/// The table of contents of an eStargz layer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Toc {
+    /// The version of the TOC format
+    pub version: i32,
+    /// The files of the layer, followed by the additional chunks of the files
+    /// that are split into several chunks
+    pub entries: Vec<TocEntry>,
+}
+
+/// An entry of the TOC of an eStargz layer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TocEntry {
+    /// The path of the file, inside of the layer
+    pub name: String,
+    /// The type of the entry: `dir`, `reg`, `symlink`, `hardlink`, `char`,
+    /// `block`, `fifo`, or `chunk` for the chunks following the first one of
+    /// a regular file
+    #[serde(rename = "type")]
+    pub entry_type: String,
+    /// The size of regular files, in bytes
+    #[serde(default)]
+    pub size: u64,
+    /// The target of links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_name: Option<String>,
+    /// The permission and mode bits
+    #[serde(default)]
+    pub mode: u32,
+    /// The offset of the gzip member holding the chunk, in the compressed
+    /// layer
+    #[serde(default)]
+    pub offset: u64,
+    /// The offset of the chunk inside of the file
+    #[serde(default)]
+    pub chunk_offset: u64,
+    /// The size of the chunk. Zero means the chunk goes up to the end of the
+    /// file.
+    #[serde(default)]
+    pub chunk_size: u64,
+    /// The digest of the content of the chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_digest: Option<String>,
+    /// The digest of the content of the whole file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+impl TocEntry {
+    fn has_content(&self) -> bool {
+        (self.entry_type == "reg" && self.size > 0) || self.entry_type == "chunk"
+    }
+}
+
+/// Return `true` when the layer is an eStargz layer, which can be pulled
+/// lazily
+pub fn is_estargz(layer: &OciDescriptor) -> bool {
+    layer.media_type.ends_with("gzip")
+        && layer
+            .annotations
+            .as_ref()
+            .is_some_and(|a| a.contains_key(TOC_DIGEST_ANNOTATION))
+}
+
+/// An eStargz layer whose TOC has been fetched, out of which files can be read
+/// without pulling the whole layer
+#[derive(Debug, Clone)]
+pub struct EStargzLayer {
+    descriptor: OciDescriptor,
+    toc: Toc,
+    toc_offset: u64,
+    // The sorted offsets of the gzip members holding chunks
+    offsets: Vec<u64>,
+}
+
+impl EStargzLayer {
+    pub(crate) fn new(descriptor: OciDescriptor, toc: Toc, toc_offset: u64) -> Self {
+        let mut offsets: Vec<u64> = toc
+            .entries
+            .iter()
+            .filter(|e| e.has_content())
+            .map(|e| e.offset)
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        EStargzLayer {
+            descriptor,
+            toc,
+            toc_offset,
+            offsets,
+        }
+    }
+
+    /// The descriptor of the layer
+    pub fn descriptor(&self) -> &OciDescriptor {
+        &self.descriptor
+    }
+
+    /// The table of contents of the layer
+    pub fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    /// Return the entry of the file at `path`, or `None` when the layer
+    /// doesn't contain it
+    pub fn entry(&self, path: &str) -> Option<&TocEntry> {
+        let path = clean_path(path);
+        self.toc
+            .entries
+            .iter()
+            .find(|e| e.entry_type != "chunk" && clean_path(&e.name) == path)
+    }
+
+    /// Return the chunks of the regular file at `path`, in order, along with
+    /// their length
+    pub(crate) fn chunks(&self, path: &str) -> Result<Vec<(&TocEntry, u64)>> {
+        let entry = self.entry(path).ok_or_else(|| {
+            OciDistributionError::EStargzError(format!("no such file in layer: {}", path))
+        })?;
+        if entry.entry_type != "reg" {
+            return Err(OciDistributionError::EStargzError(format!(
+                "not a regular file: {}",
+                path
+            )));
+        }
+        if entry.size == 0 {
+            return Ok(Vec::new());
+        }
+        let name = clean_path(&entry.name);
+        let mut chunks: Vec<&TocEntry> = self
+            .toc
+            .entries
+            .iter()
+            .filter(|e| e.has_content() && clean_path(&e.name) == name)
+            .collect();
+        chunks.sort_by_key(|e| e.chunk_offset);
+        // Only the last chunk may not have a size, and goes up to the end of
+        // the file
+        Ok(chunks
+            .into_iter()
+            .map(|chunk| {
+                let len = if chunk.chunk_size > 0 {
+                    chunk.chunk_size
+                } else {
+                    entry.size.saturating_sub(chunk.chunk_offset)
+                };
+                (chunk, len)
+            })
+            .collect())
+    }
+
+    /// The range of the compressed layer holding the given chunk
+    pub(crate) fn chunk_range(&self, chunk: &TocEntry) -> Range<u64> {
+        let end = self
+            .offsets
+            .iter()
+            .find(|&&offset| offset > chunk.offset)
+            .copied()
+            .unwrap_or(self.toc_offset);
+        chunk.offset..end
+    }
+}
+
+fn clean_path(path: &str) -> &str {
+    path.trim_start_matches("./").trim_matches('/')
+}
+
+/// Return the offset of the TOC out of the footer of an eStargz layer
+pub(crate) fn parse_footer(footer: &[u8]) -> Result<u64> {
+    let invalid = || OciDistributionError::EStargzError("invalid footer".to_string());
+    // The gzip header must have the FEXTRA flag, and an extra field made of
+    // a single "SG" subfield holding "%016xSTARGZ"
+    if footer.len() != FOOTER_SIZE as usize
+        || footer[..3] != [0x1f, 0x8b, 0x08]
+        || footer[3] & 0x04 == 0
+        || footer[10..16] != [26, 0, b'S', b'G', 22, 0]
+        || &footer[32..38] != b"STARGZ"
+    {
+        return Err(invalid());
+    }
+    let offset = std::str::from_utf8(&footer[16..32]).map_err(|_| invalid())?;
+    u64::from_str_radix(offset, 16).map_err(|_| invalid())
+}
+
+/// Decompress the gzip member holding the TOC, and parse it
+pub(crate) async fn parse_toc(compressed: &[u8]) -> Result<(Toc, Vec<u8>)> {
+    let mut tar = Vec::new();
+    GzipDecoder::new(compressed).read_to_end(&mut tar).await?;
+
+    let mut rest = tar.as_slice();
+    while rest.len() >= 512 {
+        let header = &rest[..512];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let name_len = header[..100].iter().position(|&b| b == 0).unwrap_or(100);
+        let size = std::str::from_utf8(&header[124..136])
+            .ok()
+            .map(|s| s.trim_matches(|c: char| c == '\0' || c == ' '))
+            .and_then(|s| u64::from_str_radix(s, 8).ok())
+            .ok_or_else(|| {
+                OciDistributionError::EStargzError("invalid tar header in TOC".to_string())
+            })? as usize;
+        let data = rest
+            .get(512..512 + size)
+            .ok_or_else(|| OciDistributionError::EStargzError("truncated TOC".to_string()))?;
+        if &header[..name_len] == TOC_FILE_NAME.as_bytes() {
+            let toc = serde_json::from_slice(data)?;
+            return Ok((toc, data.to_vec()));
+        }
+        rest = rest
+            .get((512 + size).div_ceil(512) * 512..)
+            .ok_or_else(|| OciDistributionError::EStargzError("truncated TOC".to_string()))?;
+    }
+    Err(OciDistributionError::EStargzError(format!(
+        "{} not found",
+        TOC_FILE_NAME
+    )))
+}
+
+/// Decompress the `len` bytes of a chunk, out of the compressed bytes starting
+/// at the offset of the chunk
+pub(crate) async fn decompress_chunk(
+    compressed: &[u8],
+    chunk: &TocEntry,
+    len: u64,
+) -> Result<Vec<u8>> {
+    let mut decoder = GzipDecoder::new(compressed);
+    decoder.multiple_members(true);
+    // The length comes from the TOC: don't trust it to allocate memory
+    let mut out = Vec::new();
+    decoder.take(len).read_to_end(&mut out).await?;
+    if out.len() as u64 != len {
+        return Err(OciDistributionError::EStargzError(format!(
+            "truncated chunk of {} at offset {}",
+            chunk.name, chunk.chunk_offset
+        )));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use async_compression::tokio::write::GzipEncoder;
+    use tokio::io::AsyncWriteExt;
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder.into_inner()
+    }
+
+    fn tar_header(name: &str, size: usize) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = b'0';
+        header
+    }
+
+    fn padding(size: usize) -> Vec<u8> {
+        vec![0u8; size.div_ceil(512) * 512 - size]
+    }
+
+    /// Build an eStargz layer holding `hello.txt`, made of two chunks, and
+    /// `empty.txt`. Returns the layer and the digest of its TOC.
+    pub(crate) async fn build_layer() -> (Vec<u8>, String) {
+        let hello = b"hello world";
+        let mut layer = gzip(&tar_header("hello.txt", hello.len())).await;
+        let first_offset = layer.len() as u64;
+        layer.extend(gzip(&hello[..6]).await);
+        let second_offset = layer.len() as u64;
+        layer.extend(gzip(&hello[6..]).await);
+        let mut tail = padding(hello.len());
+        tail.extend(tar_header("empty.txt", 0));
+        layer.extend(gzip(&tail).await);
+
+        let toc = Toc {
+            version: 1,
+            entries: vec![
+                TocEntry {
+                    name: "hello.txt".to_string(),
+                    entry_type: "reg".to_string(),
+                    size: hello.len() as u64,
+                    link_name: None,
+                    mode: 0o644,
+                    offset: first_offset,
+                    chunk_offset: 0,
+                    chunk_size: 6,
+                    chunk_digest: Some(crate::sha256_digest(&hello[..6])),
+                    digest: Some(crate::sha256_digest(hello)),
+                },
+                TocEntry {
+                    name: "empty.txt".to_string(),
+                    entry_type: "reg".to_string(),
+                    size: 0,
+                    link_name: None,
+                    mode: 0o644,
+                    offset: 0,
+                    chunk_offset: 0,
+                    chunk_size: 0,
+                    chunk_digest: None,
+                    digest: None,
+                },
+                TocEntry {
+                    name: "hello.txt".to_string(),
+                    entry_type: "chunk".to_string(),
+                    size: 0,
+                    link_name: None,
+                    mode: 0,
+                    offset: second_offset,
+                    chunk_offset: 6,
+                    chunk_size: 0,
+                    chunk_digest: Some(crate::sha256_digest(&hello[6..])),
+                    digest: None,
+                },
+            ],
+        };
+        let toc_json = serde_json::to_vec(&toc).unwrap();
+        let toc_offset = layer.len() as u64;
+        let mut toc_tar = tar_header(TOC_FILE_NAME, toc_json.len());
+        toc_tar.extend(&toc_json);
+        toc_tar.extend(padding(toc_json.len()));
+        toc_tar.extend([0u8; 1024]);
+        layer.extend(gzip(&toc_tar).await);
+
+        let mut footer = vec![
+            0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 26, 0, b'S', b'G',
+        ];
+        footer.extend([22, 0]);
+        footer.extend(format!("{:016x}STARGZ", toc_offset).as_bytes());
+        footer.extend([0x01, 0x00, 0x00, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(footer.len() as u64, FOOTER_SIZE);
+        layer.extend(footer);
+
+        (layer, crate::sha256_digest(&toc_json))
+    }
+
+    #[tokio::test]
+    async fn read_files_out_of_chunks() {
+        let (layer, _) = build_layer().await;
+        let footer = &layer[layer.len() - FOOTER_SIZE as usize..];
+        let toc_offset = parse_footer(footer).unwrap();
+        let (toc, _) = parse_toc(&layer[toc_offset as usize..layer.len() - FOOTER_SIZE as usize])
+            .await
+            .unwrap();
+
+        let descriptor = OciDescriptor {
+            media_type: crate::manifest::IMAGE_LAYER_GZIP_MEDIA_TYPE.to_string(),
+            size: layer.len() as i64,
+            ..Default::default()
+        };
+        let estargz = EStargzLayer::new(descriptor, toc, toc_offset);
+        assert_eq!(estargz.entry("./hello.txt").unwrap().size, 11);
+        assert!(estargz.entry("missing").is_none());
+        assert!(estargz.chunks("empty.txt").unwrap().is_empty());
+
+        let mut content = Vec::new();
+        for (chunk, len) in estargz.chunks("/hello.txt").unwrap() {
+            let range = estargz.chunk_range(chunk);
+            let data = &layer[range.start as usize..range.end as usize];
+            content.extend(decompress_chunk(data, chunk, len).await.unwrap());
+        }
+        assert_eq!(content, b"hello world");
+
+        // The whole layer is a valid gzip stream
+        let mut tar = Vec::new();
+        let mut decoder = GzipDecoder::new(layer.as_slice());
+        decoder.multiple_members(true);
+        decoder.read_to_end(&mut tar).await.unwrap();
+        assert_eq!(&tar[512..523], b"hello world");
+    }
+
+    #[tokio::test]
+    async fn reject_truncated_toc() {
+        // The data of the entry isn't padded to the tar block size
+        let mut tar = tar_header("a", 1);
+        tar.push(b'a');
+        assert!(matches!(
+            parse_toc(&gzip(&tar).await).await,
+            Err(OciDistributionError::EStargzError(_))
+        ));
+
+        let entry = TocEntry {
+            name: "a".to_string(),
+            entry_type: "reg".to_string(),
+            size: 1,
+            link_name: None,
+            mode: 0o644,
+            offset: 0,
+            chunk_offset: 0,
+            chunk_size: 0,
+            chunk_digest: None,
+            digest: None,
+        };
+        assert!(matches!(
+            decompress_chunk(&gzip(b"a").await, &entry, u64::MAX / 2).await,
+            Err(OciDistributionError::EStargzError(_))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_footer() {
+        assert!(parse_footer(&[0u8; FOOTER_SIZE as usize]).is_err());
+        assert!(parse_footer(&[0x1f, 0x8b, 0x08]).is_err());
+    }
+
+    #[test]
+    fn detect_estargz_layers() {
+        let mut layer = OciDescriptor {
+            media_type: crate::manifest::IMAGE_LAYER_GZIP_MEDIA_TYPE.to_string(),
+            ..Default::default()
+        };
+        assert!(!is_estargz(&layer));
+        layer.annotations = Some(
+            [(TOC_DIGEST_ANNOTATION.to_string(), "sha256:abc".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert!(is_estargz(&layer));
+        layer.media_type = crate::manifest::IMAGE_LAYER_ZSTD_MEDIA_TYPE.to_string();
+        assert!(!is_estargz(&layer));
+    }
+}
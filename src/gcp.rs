@@ -0,0 +1,114 @@
+//! Authentication against Google [Artifact Registry](https://cloud.google.com/artifact-registry)
+//! and [Container Registry](https://cloud.google.com/container-registry)
+//!
+//! These registries accept short lived OAuth2 access tokens as passwords, with
+//! the special `oauth2accesstoken` username. [`GcpAuthProvider`] mints these
+//! tokens automatically for the `gcr.io`, `*.gcr.io` and `*-docker.pkg.dev`
+//! registries.
+//!
+//! The tokens are obtained using Application Default Credentials: the service
+//! account key referenced by `GOOGLE_APPLICATION_CREDENTIALS`, the user
+//! credentials stored by `gcloud auth application-default login`, or the
+//! service account of the workload, as exposed by the metadata server (this
+//! includes GKE workload identity).
+//!
+//! Like the other [`RegistryAuthProvider`]s, [`GcpAuthProvider`] is meant to
+//! be part of a [`ChainedAuthProvider`](crate::secrets::ChainedAuthProvider).
+//!
+//! This module is available when the `gcp` feature is enabled.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use gcp_auth::TokenProvider;
+use tracing::debug;
+
+use crate::errors::{OciDistributionError, Result};
+use crate::secrets::{RegistryAuth, RegistryAuthProvider};
+
+/// The username of the credentials of GCP registries
+const GCP_USERNAME: &str = "oauth2accesstoken";
+
+/// The OAuth2 scope requested for the access tokens
+const GCP_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// Return `true` when `registry` is a Google Container Registry or Artifact
+/// Registry host.
+pub fn is_gcp_registry(registry: &str) -> bool {
+    // Ignore the port, if any
+    let host = registry.split(':').next().unwrap_or_default();
+    host == "gcr.io"
+        || host.ends_with(".gcr.io")
+        || host
+            .strip_suffix("-docker.pkg.dev")
+            .is_some_and(|location| !location.is_empty() && !location.contains('.'))
+}
+
+/// Provides the credentials of Google Cloud registries, by minting OAuth2
+/// access tokens out of the Application Default Credentials.
+pub struct GcpAuthProvider {
+    token_provider: Arc<dyn TokenProvider>,
+}
+
+impl GcpAuthProvider {
+    /// Create a provider using the Application Default Credentials.
+    ///
+    /// Fails when no credentials can be found.
+    pub async fn new() -> Result<Self> {
+        let token_provider = gcp_auth::provider().await.map_err(|e| {
+            OciDistributionError::AuthenticationFailure(format!(
+                "cannot find GCP credentials: {}",
+                e
+            ))
+        })?;
+        Ok(Self::from_token_provider(token_provider))
+    }
+
+    /// Create a provider using an already configured token provider.
+    pub fn from_token_provider(token_provider: Arc<dyn TokenProvider>) -> Self {
+        GcpAuthProvider { token_provider }
+    }
+}
+
+#[async_trait]
+impl RegistryAuthProvider for GcpAuthProvider {
+    /// Return the credentials of `registry`.
+    ///
+    /// Returns `None` when `registry` is not a Google Cloud registry. Access
+    /// tokens are cached by the token provider until they expire.
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        if !is_gcp_registry(registry) {
+            return Ok(None);
+        }
+
+        debug!(%registry, "Requesting GCP access token");
+        let token = self.token_provider.token(&[GCP_SCOPE]).await.map_err(|e| {
+            OciDistributionError::AuthenticationFailure(format!(
+                "cannot get GCP access token: {}",
+                e
+            ))
+        })?;
+
+        Ok(Some(RegistryAuth::Basic(
+            GCP_USERNAME.to_string(),
+            token.as_str().to_string(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_gcp_registries() {
+        assert!(is_gcp_registry("gcr.io"));
+        assert!(is_gcp_registry("eu.gcr.io"));
+        assert!(is_gcp_registry("europe-west1-docker.pkg.dev"));
+        assert!(is_gcp_registry("us-docker.pkg.dev:443"));
+        assert!(!is_gcp_registry("docker.pkg.dev"));
+        assert!(!is_gcp_registry("evil.com-docker.pkg.dev"));
+        assert!(!is_gcp_registry("notgcr.io"));
+        assert!(!is_gcp_registry("ghcr.io"));
+    }
+}
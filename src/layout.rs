@@ -0,0 +1,400 @@
+//! Import and export of images using the [OCI image layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md)
+//!
+//! An OCI image layout is a directory containing an `oci-layout` file, an
+//! `index.json` file referencing the manifests stored inside of the layout,
+//! and a `blobs` directory holding all the content, addressed by digest.
+//!
+//! This allows to provision images without network access: an image can be
+//! pulled with [`Client::pull`](crate::Client::pull), written to a directory
+//! with [`write_image`], shipped to an air-gapped node and loaded back with
+//! [`read_image`].
+//!
+//! *Note*: the functions of this module perform blocking filesystem operations.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use olpc_cjson::CanonicalFormatter;
+use serde::{Deserialize, Serialize};
+
+use crate::annotations::ORG_OPENCONTAINERS_IMAGE_REF_NAME;
+use crate::client::{Config, ImageData, ImageLayer};
+use crate::digest;
+use crate::errors::{OciDistributionError, Result};
+use crate::manifest::{
+    ImageIndexEntry, OciImageIndex, OciImageManifest, OCI_IMAGE_INDEX_MEDIA_TYPE,
+    OCI_IMAGE_MEDIA_TYPE,
+};
+
+/// The name of the file marking the root of an image layout
+pub const OCI_LAYOUT_FILE: &str = "oci-layout";
+/// The name of the file holding the index of an image layout
+pub const OCI_LAYOUT_INDEX_FILE: &str = "index.json";
+/// The version of the image layout written by this module
+pub const OCI_LAYOUT_VERSION: &str = "1.0.0";
+
+const BLOBS_DIR: &str = "blobs";
+
+/// The content of the `oci-layout` file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OciLayout {
+    image_layout_version: String,
+}
+
+/// Write an image to the image layout found at `path`.
+///
+/// The directory is created if it doesn't exist yet. When it already holds an
+/// image layout, the image is added to it: blobs are shared with the images
+/// already stored there, and an image previously stored under the same
+/// `ref_name` is replaced inside of `index.json`.
+///
+/// The `ref_name`, when provided, is recorded in the
+/// `org.opencontainers.image.ref.name` annotation of the index entry, and can
+/// be used to select the image with [`read_image`]. This is usually a tag.
+///
+/// If `image` doesn't include a manifest, one is generated out of its layers
+/// and config. The manifest is serialized using canonical JSON, hence its
+/// digest might differ from the one reported by the registry the image has
+/// been pulled from.
+///
+/// Returns the digest of the manifest written to the layout.
+pub fn write_image(
+    path: impl AsRef<Path>,
+    image: &ImageData,
+    ref_name: Option<&str>,
+) -> Result<String> {
+    let root = path.as_ref();
+    fs::create_dir_all(root.join(BLOBS_DIR))?;
+
+    let layout = OciLayout {
+        image_layout_version: OCI_LAYOUT_VERSION.to_string(),
+    };
+    fs::write(root.join(OCI_LAYOUT_FILE), serde_json::to_vec(&layout)?)?;
+
+    let manifest = match &image.manifest {
+        Some(m) => m.clone(),
+        None => OciImageManifest::build(&image.layers, &image.config, None),
+    };
+
+    if manifest.layers.len() != image.layers.len() {
+        return Err(OciDistributionError::ImageLayoutError(format!(
+            "the manifest describes {} layers, but the image has {}",
+            manifest.layers.len(),
+            image.layers.len()
+        )));
+    }
+    write_blob(root, &manifest.config.digest, &image.config.data)?;
+    // The layers of the image aren't necessarily in the order of the manifest
+    let sha256_digests: Vec<String> = image.layers.iter().map(|l| l.sha256_digest()).collect();
+    for descriptor in &manifest.layers {
+        let layer = if descriptor.digest.starts_with("sha256:") {
+            sha256_digests
+                .iter()
+                .position(|d| *d == descriptor.digest)
+                .map(|i| &image.layers[i])
+        } else {
+            image
+                .layers
+                .iter()
+                .find(|l| digest::verify(&l.data, &descriptor.digest).is_ok())
+        };
+        let layer = layer.ok_or_else(|| {
+            OciDistributionError::ImageLayoutError(format!(
+                "no layer of the image matches the digest {}",
+                descriptor.digest
+            ))
+        })?;
+        write_blob(root, &descriptor.digest, &layer.data)?;
+    }
+
+    // Serialize the manifest with a canonical json formatter, as described at
+    // https://github.com/opencontainers/image-spec/blob/main/considerations.md#json
+    let mut manifest_data = Vec::new();
+    let mut ser =
+        serde_json::Serializer::with_formatter(&mut manifest_data, CanonicalFormatter::new());
+    manifest.serialize(&mut ser)?;
+    let manifest_digest = crate::sha256_digest(&manifest_data);
+    write_blob(root, &manifest_digest, &manifest_data)?;
+
+    let mut index = match read_index(root) {
+        Ok(index) => index,
+        Err(OciDistributionError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            OciImageIndex {
+                schema_version: 2,
+                media_type: Some(OCI_IMAGE_INDEX_MEDIA_TYPE.to_string()),
+                manifests: Vec::new(),
+                annotations: None,
+            }
+        }
+        Err(e) => return Err(e),
+    };
+
+    let annotations = ref_name.map(|name| {
+        [(
+            ORG_OPENCONTAINERS_IMAGE_REF_NAME.to_string(),
+            name.to_string(),
+        )]
+        .into_iter()
+        .collect()
+    });
+    if let Some(name) = ref_name {
+        index
+            .manifests
+            .retain(|entry| entry_ref_name(entry) != Some(name));
+    }
+    index.manifests.push(ImageIndexEntry {
+        media_type: manifest
+            .media_type
+            .clone()
+            .unwrap_or_else(|| OCI_IMAGE_MEDIA_TYPE.to_string()),
+        digest: manifest_digest.clone(),
+        size: manifest_data.len() as i64,
+        platform: None,
+        artifact_type: manifest.artifact_type.clone(),
+        annotations,
+    });
+    fs::write(
+        root.join(OCI_LAYOUT_INDEX_FILE),
+        serde_json::to_vec_pretty(&index)?,
+    )?;
+
+    Ok(manifest_digest)
+}
+
+/// Read an image from the image layout found at `path`.
+///
+/// When `ref_name` is provided, the image is selected using the
+/// `org.opencontainers.image.ref.name` annotation of the entries of
+/// `index.json`. Otherwise the layout must contain exactly one image.
+///
+/// The digest of every blob read from the layout is verified.
+pub fn read_image(path: impl AsRef<Path>, ref_name: Option<&str>) -> Result<ImageData> {
+    let root = path.as_ref();
+
+    let layout: OciLayout = serde_json::from_slice(&fs::read(root.join(OCI_LAYOUT_FILE))?)?;
+    if layout.image_layout_version != OCI_LAYOUT_VERSION {
+        return Err(OciDistributionError::ImageLayoutError(format!(
+            "unsupported image layout version {}",
+            layout.image_layout_version
+        )));
+    }
+
+    let index = read_index(root)?;
+    let entry = match ref_name {
+        Some(name) => index
+            .manifests
+            .iter()
+            .find(|entry| entry_ref_name(entry) == Some(name))
+            .ok_or_else(|| {
+                OciDistributionError::ImageLayoutError(format!("no image named {}", name))
+            })?,
+        None => match index.manifests.as_slice() {
+            [entry] => entry,
+            entries => {
+                return Err(OciDistributionError::ImageLayoutError(format!(
+                    "expected exactly one image, found {}",
+                    entries.len()
+                )))
+            }
+        },
+    };
+
+    let manifest: OciImageManifest = serde_json::from_slice(&read_blob(root, &entry.digest)?)
+        .map_err(|e| OciDistributionError::ManifestParsingError(e.to_string()))?;
+
+    let config = Config::new(
+        read_blob(root, &manifest.config.digest)?,
+        manifest.config.media_type.clone(),
+        manifest.annotations.clone(),
+    );
+    let layers = manifest
+        .layers
+        .iter()
+        .map(|layer| {
+            Ok(ImageLayer::new(
+                read_blob(root, &layer.digest)?,
+                layer.media_type.clone(),
+                layer.annotations.clone(),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ImageData {
+        layers,
+        digest: Some(entry.digest.clone()),
+        config,
+        manifest: Some(manifest),
+    })
+}
+
+fn entry_ref_name(entry: &ImageIndexEntry) -> Option<&str> {
+    entry
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(ORG_OPENCONTAINERS_IMAGE_REF_NAME))
+        .map(String::as_str)
+}
+
+fn read_index(root: &Path) -> Result<OciImageIndex> {
+    let data = fs::read(root.join(OCI_LAYOUT_INDEX_FILE))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Compute the path of a blob inside of the layout, rejecting digests that
+/// could escape the `blobs` directory
+fn blob_path(root: &Path, digest: &str) -> Result<PathBuf> {
+    match digest.split_once(':') {
+        Some((algorithm, encoded))
+            if !algorithm.is_empty()
+                && !encoded.is_empty()
+                && algorithm.chars().all(|c| c.is_ascii_alphanumeric())
+                && encoded.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            Ok(root.join(BLOBS_DIR).join(algorithm).join(encoded))
+        }
+        _ => Err(OciDistributionError::ImageLayoutError(format!(
+            "invalid digest {}",
+            digest
+        ))),
+    }
+}
+
+fn write_blob(root: &Path, digest: &str, data: &[u8]) -> Result<()> {
+    let path = blob_path(root, digest)?;
+    if path.exists() {
+        return Ok(());
+    }
+    digest::verify(data, digest)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
+fn read_blob(root: &Path, digest: &str) -> Result<Vec<u8>> {
+    let data = fs::read(blob_path(root, digest)?)?;
+    digest::verify(&data, digest)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::{WASM_CONFIG_MEDIA_TYPE, WASM_LAYER_MEDIA_TYPE};
+
+    fn image(module: &[u8]) -> ImageData {
+        ImageData {
+            layers: vec![ImageLayer::new(
+                module.to_vec(),
+                WASM_LAYER_MEDIA_TYPE.to_string(),
+                None,
+            )],
+            digest: None,
+            config: Config::new(b"{}".to_vec(), WASM_CONFIG_MEDIA_TYPE.to_string(), None),
+            manifest: None,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let v1 = image(b"first module");
+        let v2 = image(b"second module");
+
+        let v1_digest = write_image(dir.path(), &v1, Some("v1")).expect("write v1");
+        let v2_digest = write_image(dir.path(), &v2, Some("v2")).expect("write v2");
+        assert!(dir.path().join(OCI_LAYOUT_FILE).exists());
+
+        let read = read_image(dir.path(), Some("v1")).expect("read v1");
+        assert_eq!(read.digest, Some(v1_digest));
+        assert_eq!(read.layers[0].data, b"first module");
+        assert_eq!(read.layers[0].media_type, WASM_LAYER_MEDIA_TYPE);
+        assert_eq!(read.config.data, b"{}");
+        assert_eq!(read.config.media_type, WASM_CONFIG_MEDIA_TYPE);
+
+        let read = read_image(dir.path(), Some("v2")).expect("read v2");
+        assert_eq!(read.digest, Some(v2_digest));
+        assert_eq!(read.layers[0].data, b"second module");
+
+        // Two images are stored, one must be selected
+        assert!(read_image(dir.path(), None).is_err());
+        assert!(read_image(dir.path(), Some("v3")).is_err());
+    }
+
+    #[test]
+    fn write_layers_out_of_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let layers: Vec<ImageLayer> = [b"first layer", b"other layer", b"third layer"]
+            .iter()
+            .map(|data| ImageLayer::new(data.to_vec(), WASM_LAYER_MEDIA_TYPE.to_string(), None))
+            .collect();
+        let config = Config::new(b"{}".to_vec(), WASM_CONFIG_MEDIA_TYPE.to_string(), None);
+        let manifest = OciImageManifest::build(&layers, &config, None);
+        // The layers of pulled images arrive in the order they are downloaded
+        let pulled = ImageData {
+            layers: vec![layers[2].clone(), layers[0].clone(), layers[1].clone()],
+            digest: None,
+            config,
+            manifest: Some(manifest),
+        };
+        write_image(dir.path(), &pulled, None).expect("write");
+
+        let read = read_image(dir.path(), None).expect("read");
+        let data: Vec<&[u8]> = read.layers.iter().map(|l| l.data.as_slice()).collect();
+        assert_eq!(
+            data,
+            vec![
+                &b"first layer"[..],
+                &b"other layer"[..],
+                &b"third layer"[..]
+            ]
+        );
+
+        let mut missing = pulled.clone();
+        missing.layers[0] = layers[0].clone();
+        assert!(matches!(
+            write_image(tempfile::tempdir().unwrap().path(), &missing, None),
+            Err(OciDistributionError::ImageLayoutError(_))
+        ));
+    }
+
+    #[test]
+    fn write_replaces_existing_ref_name() {
+        let dir = tempfile::tempdir().unwrap();
+        write_image(dir.path(), &image(b"first module"), Some("latest")).expect("write");
+        let digest =
+            write_image(dir.path(), &image(b"second module"), Some("latest")).expect("write");
+
+        let read = read_image(dir.path(), None).expect("read single image");
+        assert_eq!(read.digest, Some(digest));
+        assert_eq!(read.layers[0].data, b"second module");
+    }
+
+    #[test]
+    fn read_detects_corrupted_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let module = image(b"a module");
+        write_image(dir.path(), &module, None).expect("write");
+
+        let layer_digest = module.layers[0].sha256_digest();
+        fs::write(blob_path(dir.path(), &layer_digest).unwrap(), b"corrupted").unwrap();
+
+        assert!(matches!(
+            read_image(dir.path(), None),
+            Err(OciDistributionError::DigestMismatchError { .. })
+        ));
+    }
+
+    #[test]
+    fn blob_path_rejects_traversal() {
+        let root = Path::new("/layout");
+        assert!(blob_path(root, "sha256:../../etc/passwd").is_err());
+        assert!(blob_path(root, "../sha256:abcd").is_err());
+        assert_eq!(
+            blob_path(root, "sha256:abcd").unwrap(),
+            Path::new("/layout/blobs/sha256/abcd")
+        );
+    }
+}
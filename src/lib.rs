@@ -3,14 +3,31 @@
 
 use sha2::Digest;
 
+#[cfg(feature = "acr")]
+pub mod acr;
 pub mod annotations;
+pub mod cache;
 pub mod client;
+pub mod compression;
 pub mod config;
+mod digest;
+#[cfg(feature = "ecr")]
+pub mod ecr;
 pub mod errors;
+pub mod estargz;
+#[cfg(feature = "gcp")]
+pub mod gcp;
+pub mod layout;
 pub mod manifest;
+#[cfg(feature = "notation")]
+pub mod notation;
 mod reference;
 mod regexp;
+pub mod retry;
+mod schema1;
 pub mod secrets;
+#[cfg(feature = "sigstore")]
+pub mod sigstore;
 mod token_cache;
 
 #[doc(inline)]
@@ -15,6 +15,13 @@ pub const IMAGE_MANIFEST_MEDIA_TYPE: &str = "application/vnd.docker.distribution
 /// The mediatype for an docker v2 shema 2 manifest list.
 pub const IMAGE_MANIFEST_LIST_MEDIA_TYPE: &str =
     "application/vnd.docker.distribution.manifest.list.v2+json";
+/// The mediatype for a signed docker v2 schema 1 manifest, served by legacy
+/// registries.
+pub const IMAGE_MANIFEST_SCHEMA1_SIGNED_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.v1+prettyjws";
+/// The mediatype for an unsigned docker v2 schema 1 manifest.
+pub const IMAGE_MANIFEST_SCHEMA1_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.v1+json";
 /// The mediatype for an OCI image index manifest.
 pub const OCI_IMAGE_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
 /// The mediatype for an OCI image manifest.
@@ -27,21 +34,70 @@ pub const IMAGE_DOCKER_CONFIG_MEDIA_TYPE: &str = "application/vnd.docker.contain
 pub const IMAGE_LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar";
 /// The mediatype for a layer that is gzipped.
 pub const IMAGE_LAYER_GZIP_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+/// The mediatype for a layer that is compressed with zstd.
+pub const IMAGE_LAYER_ZSTD_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
 /// The mediatype that Docker uses for a layer that is tarred.
 pub const IMAGE_DOCKER_LAYER_TAR_MEDIA_TYPE: &str = "application/vnd.docker.image.rootfs.diff.tar";
 /// The mediatype that Docker uses for a layer that is gzipped.
 pub const IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE: &str =
     "application/vnd.docker.image.rootfs.diff.tar.gzip";
+/// The mediatype that Docker uses for a foreign layer, which is tarred and
+/// can't be pushed to registries.
+pub const IMAGE_DOCKER_LAYER_FOREIGN_TAR_MEDIA_TYPE: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar";
+/// The mediatype that Docker uses for a foreign layer that is gzipped.
+pub const IMAGE_DOCKER_LAYER_FOREIGN_GZIP_MEDIA_TYPE: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
 /// The mediatype for a layer that is nondistributable.
 pub const IMAGE_LAYER_NONDISTRIBUTABLE_MEDIA_TYPE: &str =
     "application/vnd.oci.image.layer.nondistributable.v1.tar";
 /// The mediatype for a layer that is nondistributable and gzipped.
 pub const IMAGE_LAYER_NONDISTRIBUTABLE_GZIP_MEDIA_TYPE: &str =
     "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip";
+/// The mediatype for a layer that is nondistributable and compressed with zstd.
+pub const IMAGE_LAYER_NONDISTRIBUTABLE_ZSTD_MEDIA_TYPE: &str =
+    "application/vnd.oci.image.layer.nondistributable.v1.tar+zstd";
+
+/// Return `true` for the media types of the layers that can't be pushed to
+/// registries, such as the foreign layers of Windows base images. These layers
+/// are usually downloaded from the `urls` of their descriptor.
+pub fn is_non_distributable(media_type: &str) -> bool {
+    media_type.contains(".foreign.diff") || media_type.contains(".nondistributable.")
+}
+
+/// Return the media type of the distributable layers the non-distributable
+/// layers of the given media type are equivalent to, or `None` when the media
+/// type isn't non-distributable.
+///
+/// For instance, foreign layers of media type
+/// `application/vnd.docker.image.rootfs.foreign.diff.tar.gzip` are regular
+/// `application/vnd.docker.image.rootfs.diff.tar.gzip` layers.
+pub fn distributable_media_type(media_type: &str) -> Option<String> {
+    if media_type.contains(".foreign.diff") {
+        Some(media_type.replacen(".foreign.diff", ".diff", 1))
+    } else if media_type.contains(".nondistributable.") {
+        Some(media_type.replacen(".nondistributable.", ".", 1))
+    } else {
+        None
+    }
+}
+
+/// The media type of the empty config of artifacts that don't need one
+pub const OCI_EMPTY_CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+/// The content of the empty config of artifacts
+pub const OCI_EMPTY_CONFIG_DATA: &[u8] = b"{}";
+
+/// The artifact type of SPDX software bills of materials in JSON format
+pub const SBOM_SPDX_ARTIFACT_TYPE: &str = "application/spdx+json";
+/// The artifact type of CycloneDX software bills of materials in JSON format
+pub const SBOM_CYCLONEDX_ARTIFACT_TYPE: &str = "application/vnd.cyclonedx+json";
+/// The artifact type of in-toto attestations, such as SLSA provenance
+pub const IN_TOTO_ARTIFACT_TYPE: &str = "application/vnd.in-toto+json";
 
 /// An image, or image index, OCI manifest
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum OciManifest {
     /// An OCI image manifest
     Image(OciImageManifest),
@@ -107,6 +163,16 @@ pub struct OciImageManifest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub artifact_type: Option<String>,
 
+    /// The manifest this manifest refers to
+    ///
+    /// This OPTIONAL property specifies a descriptor of another manifest. It
+    /// is used to attach artifacts, such as signatures or SBOMs, to an image.
+    /// They can then be found using the referrers API.
+    ///
+    /// Introduced in OCI Image Format spec v1.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<OciDescriptor>,
+
     /// The annotations for this manifest
     ///
     /// The specification says "If there are no annotations then this property
@@ -124,6 +190,7 @@ impl Default for OciImageManifest {
             config: OciDescriptor::default(),
             layers: vec![],
             artifact_type: None,
+            subject: None,
             annotations: None,
         }
     }
@@ -162,6 +229,26 @@ impl OciImageManifest {
 
         manifest
     }
+
+    /// Create a new OciImageManifest describing an artifact of type
+    /// `artifact_type`, such as a signature, an SBOM or a policy bundle.
+    ///
+    /// When the artifact doesn't have a config, the empty config is used:
+    /// [`Config::oci_empty`] must then be pushed along with the manifest.
+    /// The `subject` of the manifest can be set to attach the artifact to
+    /// another manifest.
+    pub fn build_artifact(
+        artifact_type: &str,
+        layers: &[ImageLayer],
+        config: Option<&Config>,
+        annotations: Option<HashMap<String, String>>,
+    ) -> Self {
+        let empty_config = Config::oci_empty();
+        let mut manifest = Self::build(layers, config.unwrap_or(&empty_config), annotations);
+        manifest.media_type = Some(OCI_IMAGE_MEDIA_TYPE.to_string());
+        manifest.artifact_type = Some(artifact_type.to_string());
+        manifest
+    }
 }
 
 impl From<OciImageIndex> for OciManifest {
@@ -277,6 +364,14 @@ pub struct OciDescriptor {
     /// <https://github.com/opencontainers/image-spec/blob/main/annotations.md#rules>
     #[serde(skip_serializing_if = "Option::is_none")]
     pub annotations: Option<HashMap<String, String>>,
+
+    /// This OPTIONAL property contains an embedded representation of the
+    /// referenced content, encoded in base64. When set, the content doesn't
+    /// need to be fetched from the registry.
+    ///
+    /// Introduced in OCI Image Format spec v1.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
 }
 
 impl std::fmt::Display for OciDescriptor {
@@ -300,6 +395,7 @@ impl Default for OciDescriptor {
             size: 0,
             urls: None,
             annotations: None,
+            data: None,
         }
     }
 }
@@ -372,6 +468,14 @@ pub struct ImageIndexEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub platform: Option<Platform>,
 
+    /// This OPTIONAL property contains the type of an artifact when the
+    /// descriptor points to an artifact. It is set on the entries returned by
+    /// the referrers API.
+    ///
+    /// Introduced in OCI Image Format spec v1.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+
     /// This OPTIONAL property contains arbitrary metadata for the image index.
     /// This OPTIONAL property MUST use the [annotation rules](https://github.com/opencontainers/image-spec/blob/main/annotations.md#rules).
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -421,6 +525,7 @@ pub struct Platform {
     /// This OPTIONAL property specifies an array of strings, each specifying a mandatory OS feature.
     /// When `os` is `windows`, image indexes SHOULD use, and implementations SHOULD understand the following values:
     /// - `win32k`: image requires `win32k.sys` on the host (Note: `win32k.sys` is missing on Nano Server)
+    ///
     /// When `os` is not `windows`, values are implementation-defined and SHOULD be submitted to this specification for standardization.
     #[serde(rename = "os.features")]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -515,4 +620,62 @@ mod test {
                 .len()
         );
     }
+
+    #[test]
+    fn test_non_distributable_media_types() {
+        assert!(is_non_distributable(
+            IMAGE_DOCKER_LAYER_FOREIGN_GZIP_MEDIA_TYPE
+        ));
+        assert!(is_non_distributable(
+            IMAGE_LAYER_NONDISTRIBUTABLE_ZSTD_MEDIA_TYPE
+        ));
+        assert!(!is_non_distributable(IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE));
+        assert!(!is_non_distributable(IMAGE_LAYER_GZIP_MEDIA_TYPE));
+
+        assert_eq!(
+            distributable_media_type(IMAGE_DOCKER_LAYER_FOREIGN_GZIP_MEDIA_TYPE).as_deref(),
+            Some(IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE)
+        );
+        assert_eq!(
+            distributable_media_type(IMAGE_DOCKER_LAYER_FOREIGN_TAR_MEDIA_TYPE).as_deref(),
+            Some(IMAGE_DOCKER_LAYER_TAR_MEDIA_TYPE)
+        );
+        assert_eq!(
+            distributable_media_type(IMAGE_LAYER_NONDISTRIBUTABLE_GZIP_MEDIA_TYPE).as_deref(),
+            Some(IMAGE_LAYER_GZIP_MEDIA_TYPE)
+        );
+        assert_eq!(distributable_media_type(IMAGE_LAYER_MEDIA_TYPE), None);
+    }
+
+    #[test]
+    fn test_build_artifact() {
+        let layers = vec![ImageLayer::new(
+            b"signature".to_vec(),
+            "application/vnd.dev.cosign.simplesigning.v1+json".to_string(),
+            None,
+        )];
+        let mut manifest = OciImageManifest::build_artifact(
+            "application/vnd.example.signature",
+            &layers,
+            None,
+            None,
+        );
+        manifest.subject = Some(OciDescriptor {
+            media_type: OCI_IMAGE_MEDIA_TYPE.to_string(),
+            digest: "sha256:deadbeef".to_string(),
+            size: 42,
+            ..Default::default()
+        });
+
+        let json: serde_json::Value = serde_json::to_value(&manifest).expect("serialized");
+        assert_eq!(json["mediaType"], OCI_IMAGE_MEDIA_TYPE);
+        assert_eq!(json["artifactType"], "application/vnd.example.signature");
+        assert_eq!(json["config"]["mediaType"], OCI_EMPTY_CONFIG_MEDIA_TYPE);
+        assert_eq!(
+            json["config"]["digest"],
+            sha256_digest(OCI_EMPTY_CONFIG_DATA)
+        );
+        assert_eq!(json["config"]["size"], 2);
+        assert_eq!(json["subject"]["digest"], "sha256:deadbeef");
+    }
 }
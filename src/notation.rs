@@ -0,0 +1,1232 @@
+//! Verification of [notation](https://notaryproject.dev) signatures according
+//! to a trust policy
+//!
+//! Notation attaches signatures to images as artifacts of type
+//! `application/vnd.cncf.notary.signature`, which are found through the
+//! referrers API. Each signature is a JWS envelope signed by an X.509
+//! certificate, whose chain must lead to a certificate of a trust store.
+//!
+//! Which signatures are trusted is decided by a [`TrustPolicyDocument`], using
+//! the same `trustpolicy.json` format as the notation CLI: every policy applies
+//! to some registry scopes, and lists the trust stores and the identities that
+//! are trusted to sign the images of these scopes. The verification level of a
+//! policy decides what happens when an image isn't properly signed:
+//!
+//! * `strict`: the image is rejected
+//! * `permissive`: the image is rejected, unless the only problem is that the
+//!   signature or its certificates have expired, which is logged
+//! * `audit`: failures are logged, the image is accepted
+//! * `skip`: signatures are not verified
+//!
+//! Signatures can be verified explicitly with
+//! [`Client::verify_notation_signature`], or on every pull by setting
+//! [`ClientConfig::notation_verifier`](crate::client::ClientConfig::notation_verifier).
+//!
+//! Only the JWS envelope format and the `notary.x509` signing scheme are
+//! supported, with ECDSA P-256 and P-384 keys.
+//!
+//! This module is available when the `notation` feature is enabled.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::ecdsa::signature::Verifier;
+use p256::pkcs8::DecodePublicKey;
+use serde::Deserialize;
+use sha2::Digest;
+use tracing::{debug, warn};
+use x509_cert::attr::AttributeTypeAndValue;
+use x509_cert::der::oid::db::rfc5280::ID_KP_CODE_SIGNING;
+use x509_cert::der::oid::AssociatedOid;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::{BasicConstraints, ExtendedKeyUsage, KeyUsage};
+use x509_cert::Certificate;
+
+use crate::client::Client;
+use crate::errors::{OciDistributionError, Result};
+use crate::secrets::RegistryAuth;
+use crate::Reference;
+
+/// The artifact type of notation signatures
+pub const NOTATION_SIGNATURE_ARTIFACT_TYPE: &str = "application/vnd.cncf.notary.signature";
+/// The media type of signatures using the JWS envelope format
+pub const NOTATION_JWS_MEDIA_TYPE: &str = "application/jose+json";
+
+/// The content type of the payloads signed by notation
+const NOTATION_PAYLOAD_CONTENT_TYPE: &str = "application/vnd.cncf.notary.payload.v1+json";
+/// The only supported signing scheme
+const NOTATION_SIGNING_SCHEME: &str = "notary.x509";
+/// The protected header holding the signing scheme
+const SIGNING_SCHEME_HEADER: &str = "io.cncf.notary.signingScheme";
+/// The protected header holding the expiry of the signature
+const EXPIRY_HEADER: &str = "io.cncf.notary.expiry";
+/// The critical headers understood by the verifier
+const NOTATION_CRITICAL_HEADERS: &[&str] = &[SIGNING_SCHEME_HEADER, EXPIRY_HEADER];
+/// The prefix of the trusted identities matching the subject of certificates
+const X509_SUBJECT_PREFIX: &str = "x509.subject:";
+
+/// A trust policy document, as found in the `trustpolicy.json` file of
+/// notation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustPolicyDocument {
+    /// The version of the document
+    pub version: String,
+    /// The policies of the document
+    pub trust_policies: Vec<TrustPolicy>,
+}
+
+/// A policy deciding how the images of some registry scopes are verified
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustPolicy {
+    /// The name of the policy
+    pub name: String,
+    /// The repositories the policy applies to, such as
+    /// `registry.example.com/app`, or `*` for all of them
+    pub registry_scopes: Vec<String>,
+    /// How signatures are verified
+    pub signature_verification: SignatureVerification,
+    /// The trust stores, such as `ca:acme-rockets`, holding the root
+    /// certificates signatures must chain to
+    #[serde(default)]
+    pub trust_stores: Vec<String>,
+    /// The identities trusted to sign images, such as
+    /// `x509.subject: C=US, O=Acme Rockets`, or `*` for all of them
+    #[serde(default)]
+    pub trusted_identities: Vec<String>,
+}
+
+/// The signature verification settings of a [`TrustPolicy`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignatureVerification {
+    /// The verification level
+    pub level: VerificationLevel,
+}
+
+/// What happens when an image isn't properly signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationLevel {
+    /// Reject the image
+    Strict,
+    /// Reject the image, unless the signature has only expired
+    Permissive,
+    /// Log failures, accept the image
+    Audit,
+    /// Don't verify signatures
+    Skip,
+}
+
+impl TrustPolicyDocument {
+    /// Read a trust policy document from a `trustpolicy.json` file
+    ///
+    /// *Note*: this function performs blocking I/O operations.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Return the policy applying to `reference`: the policy whose scopes
+    /// include its repository, or the one using the `*` wildcard scope
+    pub fn policy_for(&self, reference: &Reference) -> Option<&TrustPolicy> {
+        let scope = format!("{}/{}", reference.registry(), reference.repository());
+        self.trust_policies
+            .iter()
+            .find(|p| p.registry_scopes.contains(&scope))
+            .or_else(|| {
+                self.trust_policies
+                    .iter()
+                    .find(|p| p.registry_scopes.iter().any(|s| s == "*"))
+            })
+    }
+}
+
+/// Named sets of trusted root certificates, referenced by the trust stores of
+/// a [`TrustPolicy`]
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    certificates: HashMap<String, Vec<Certificate>>,
+}
+
+impl TrustStore {
+    /// Create an empty trust store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the PEM encoded certificates of the trust store named `name`,
+    /// such as `ca:acme-rockets`
+    pub fn with_certificates_pem(mut self, name: &str, pem: &str) -> Result<Self> {
+        let certificates = Certificate::load_pem_chain(pem.as_bytes()).map_err(|e| {
+            OciDistributionError::SignatureVerificationError(format!(
+                "cannot parse certificates of trust store {}: {}",
+                name, e
+            ))
+        })?;
+        self.certificates
+            .entry(name.to_string())
+            .or_default()
+            .extend(certificates);
+        Ok(self)
+    }
+}
+
+/// Verifies notation signatures according to a trust policy
+#[derive(Debug, Clone)]
+pub struct NotationVerifier {
+    trust_policy: TrustPolicyDocument,
+    trust_store: TrustStore,
+}
+
+impl NotationVerifier {
+    /// Create a verifier enforcing `trust_policy`, using the root
+    /// certificates of `trust_store`
+    pub fn new(trust_policy: TrustPolicyDocument, trust_store: TrustStore) -> Self {
+        NotationVerifier {
+            trust_policy,
+            trust_store,
+        }
+    }
+}
+
+impl Client {
+    /// Return `true` when the image with the given digest, found in the image
+    /// cache, can be used without verifying its signatures: its policy skips
+    /// or only audits the verification, or a trusted signature of its
+    /// manifest has been verified when it was pulled.
+    pub(crate) fn is_cached_image_trusted(
+        &self,
+        verifier: &NotationVerifier,
+        image: &Reference,
+        digest: &str,
+    ) -> bool {
+        let level = verifier
+            .trust_policy
+            .policy_for(image)
+            .map(|p| p.signature_verification.level);
+        matches!(
+            level,
+            Some(VerificationLevel::Skip | VerificationLevel::Audit)
+        ) || (level.is_some()
+            && self
+                .config
+                .image_cache
+                .as_ref()
+                .is_some_and(|cache| cache.is_verified(digest)))
+    }
+
+    /// Verify the notation signatures of the given Reference, according to
+    /// the trust policy of `verifier`
+    ///
+    /// The client will check if it's already been authenticated and if
+    /// not will attempt to do.
+    ///
+    /// Fails when no policy applies to the image, or when the image isn't
+    /// signed as required by the verification level of its policy.
+    ///
+    /// Returns the digest of the manifest whose signatures were verified,
+    /// which should be used to pull the image instead of its tag, or `None`
+    /// when the policy skips the verification.
+    pub async fn verify_notation_signature(
+        &mut self,
+        image: &Reference,
+        auth: &RegistryAuth,
+        verifier: &NotationVerifier,
+    ) -> Result<Option<String>> {
+        let policy = verifier.trust_policy.policy_for(image).ok_or_else(|| {
+            OciDistributionError::SignatureVerificationError(format!(
+                "no trust policy applies to {}",
+                image
+            ))
+        })?;
+        let level = policy.signature_verification.level;
+        if level == VerificationLevel::Skip {
+            debug!(%image, policy = %policy.name, "Skipping signature verification");
+            return Ok(None);
+        }
+
+        let digest = match image.digest() {
+            Some(digest) => digest.to_string(),
+            None => self.fetch_manifest_digest(image, auth).await?,
+        };
+        let subject = Reference::with_digest(
+            image.registry().to_string(),
+            image.repository().to_string(),
+            digest.clone(),
+        );
+
+        // Failures to pull the signatures are collected as well, so that they
+        // are only logged in audit mode
+        let mut failures = Vec::new();
+        let referrers = match self
+            .list_referrers(&subject, auth, Some(NOTATION_SIGNATURE_ARTIFACT_TYPE))
+            .await
+        {
+            Ok(referrers) => referrers,
+            Err(e) => {
+                failures.push(format!("cannot list the signatures: {}", e));
+                Vec::new()
+            }
+        };
+        for referrer in referrers {
+            let reference = Reference::with_digest(
+                image.registry().to_string(),
+                image.repository().to_string(),
+                referrer.digest,
+            );
+            let manifest = match self.pull_image_manifest(&reference, auth).await {
+                Ok((manifest, _)) => manifest,
+                Err(e) => {
+                    failures.push(format!("cannot pull the signature {}: {}", reference, e));
+                    continue;
+                }
+            };
+            for layer in &manifest.layers {
+                if layer.media_type != NOTATION_JWS_MEDIA_TYPE {
+                    failures.push(format!("unsupported envelope type {}", layer.media_type));
+                    continue;
+                }
+                let mut envelope = Vec::new();
+                if let Err(e) = self
+                    .pull_blob(&reference, &layer.digest, &mut envelope)
+                    .await
+                {
+                    failures.push(format!("cannot pull the envelope {}: {}", layer.digest, e));
+                    continue;
+                }
+                match verify_envelope(
+                    policy,
+                    &verifier.trust_store,
+                    &envelope,
+                    &digest,
+                    SystemTime::now(),
+                ) {
+                    Ok(()) => {
+                        debug!(%image, policy = %policy.name, "Verified notation signature");
+                        if let Some(cache) = &self.config.image_cache {
+                            if let Err(e) = cache.set_verified(&digest) {
+                                warn!(%digest, error = %e, "Cannot record verified signature in image cache");
+                            }
+                        }
+                        return Ok(Some(digest));
+                    }
+                    Err(e) => failures.push(e.to_string()),
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            failures.push("the image is not signed".to_string());
+        }
+        let error = OciDistributionError::SignatureVerificationError(format!(
+            "no trusted notation signature found for {}: {}",
+            image,
+            failures.join("; ")
+        ));
+        if level == VerificationLevel::Audit {
+            warn!(%image, policy = %policy.name, %error, "Accepting image in audit mode");
+            Ok(Some(digest))
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// A JWS envelope, using the flattened JSON serialization
+#[derive(Deserialize)]
+struct JwsEnvelope {
+    payload: String,
+    protected: String,
+    header: UnprotectedHeader,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct UnprotectedHeader {
+    /// The certificate chain, starting with the signing certificate
+    x5c: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ProtectedHeader {
+    alg: String,
+    /// The headers that must be understood to verify the signature
+    #[serde(default)]
+    crit: Vec<String>,
+    cty: String,
+    #[serde(rename = "io.cncf.notary.signingScheme")]
+    signing_scheme: String,
+    #[serde(rename = "io.cncf.notary.expiry")]
+    expiry: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NotationPayload {
+    target_artifact: TargetArtifact,
+}
+
+#[derive(Deserialize)]
+struct TargetArtifact {
+    digest: String,
+}
+
+/// An ECDSA public key
+enum PublicKey {
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+}
+
+fn verification_error(message: impl Into<String>) -> OciDistributionError {
+    OciDistributionError::SignatureVerificationError(message.into())
+}
+
+/// Verify a signature envelope against a policy. Expiration problems are
+/// only fatal for the strict verification level.
+fn verify_envelope(
+    policy: &TrustPolicy,
+    trust_store: &TrustStore,
+    envelope: &[u8],
+    expected_digest: &str,
+    now: SystemTime,
+) -> Result<()> {
+    let b64url = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let envelope: JwsEnvelope = serde_json::from_slice(envelope)
+        .map_err(|e| verification_error(format!("cannot parse signature envelope: {}", e)))?;
+    let header: ProtectedHeader = b64url
+        .decode(&envelope.protected)
+        .ok()
+        .and_then(|h| serde_json::from_slice(&h).ok())
+        .ok_or_else(|| verification_error("cannot parse the protected header"))?;
+    if header.cty != NOTATION_PAYLOAD_CONTENT_TYPE {
+        return Err(verification_error(format!(
+            "unexpected payload content type {}",
+            header.cty
+        )));
+    }
+    if header.signing_scheme != NOTATION_SIGNING_SCHEME {
+        return Err(verification_error(format!(
+            "unsupported signing scheme {}",
+            header.signing_scheme
+        )));
+    }
+    if let Some(unknown) = header
+        .crit
+        .iter()
+        .find(|h| !NOTATION_CRITICAL_HEADERS.contains(&h.as_str()))
+    {
+        return Err(verification_error(format!(
+            "unsupported critical header {}",
+            unknown
+        )));
+    }
+    let mut required = vec![SIGNING_SCHEME_HEADER];
+    if header.expiry.is_some() {
+        required.push(EXPIRY_HEADER);
+    }
+    if let Some(missing) = required
+        .iter()
+        .find(|h| !header.crit.iter().any(|c| c == *h))
+    {
+        return Err(verification_error(format!(
+            "the header {} must be marked as critical",
+            missing
+        )));
+    }
+
+    let chain = envelope
+        .header
+        .x5c
+        .iter()
+        .map(|c| {
+            base64::engine::general_purpose::STANDARD
+                .decode(c)
+                .ok()
+                .and_then(|der| Certificate::from_der(&der).ok())
+                .ok_or_else(|| verification_error("cannot parse the certificate chain"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| verification_error("the certificate chain is empty"))?;
+
+    // Integrity: the payload has been signed by the signing certificate
+    let signature = b64url
+        .decode(&envelope.signature)
+        .map_err(|_| verification_error("cannot decode the signature"))?;
+    let signing_input = format!("{}.{}", envelope.protected, envelope.payload);
+    verify_jws_signature(
+        &header.alg,
+        &public_key(leaf)?,
+        signing_input.as_bytes(),
+        &signature,
+    )?;
+
+    // Authenticity: the certificate chain leads to a trusted root, and the
+    // signing certificate belongs to a trusted identity
+    verify_chain(&chain, policy, trust_store)?;
+    verify_identity(leaf, policy)?;
+
+    let payload: NotationPayload = b64url
+        .decode(&envelope.payload)
+        .ok()
+        .and_then(|p| serde_json::from_slice(&p).ok())
+        .ok_or_else(|| verification_error("cannot parse the signed payload"))?;
+    if payload.target_artifact.digest != expected_digest {
+        return Err(verification_error(format!(
+            "the signature is for {}, not {}",
+            payload.target_artifact.digest, expected_digest
+        )));
+    }
+
+    // Expiry
+    let mut expired = Vec::new();
+    if let Some(expiry) = header.expiry {
+        if SystemTime::from(expiry) < now {
+            expired.push(format!("the signature expired at {}", expiry));
+        }
+    }
+    for certificate in &chain {
+        let validity = &certificate.tbs_certificate.validity;
+        let not_before = SystemTime::UNIX_EPOCH + validity.not_before.to_unix_duration();
+        let not_after = SystemTime::UNIX_EPOCH + validity.not_after.to_unix_duration();
+        if now < not_before || now > not_after {
+            expired.push(format!(
+                "the certificate {} is not valid at this time",
+                certificate.tbs_certificate.subject
+            ));
+        }
+    }
+    if !expired.is_empty() {
+        let message = expired.join(", ");
+        match policy.signature_verification.level {
+            VerificationLevel::Strict => return Err(verification_error(message)),
+            _ => warn!(policy = %policy.name, %message, "Ignoring expired signature"),
+        }
+    }
+
+    Ok(())
+}
+
+fn public_key(certificate: &Certificate) -> Result<PublicKey> {
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| verification_error(format!("cannot encode public key: {}", e)))?;
+    if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_der(&spki) {
+        Ok(PublicKey::P256(key))
+    } else if let Ok(key) = p384::ecdsa::VerifyingKey::from_public_key_der(&spki) {
+        Ok(PublicKey::P384(key))
+    } else {
+        Err(verification_error(format!(
+            "unsupported public key of certificate {}",
+            certificate.tbs_certificate.subject
+        )))
+    }
+}
+
+/// Verify a JWS signature, which is the concatenation of the `r` and `s`
+/// values of the ECDSA signature
+fn verify_jws_signature(
+    alg: &str,
+    key: &PublicKey,
+    signing_input: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let valid = match (alg, key) {
+        ("ES256", PublicKey::P256(key)) => p256::ecdsa::Signature::from_slice(signature)
+            .is_ok_and(|s| key.verify(signing_input, &s).is_ok()),
+        ("ES384", PublicKey::P384(key)) => p384::ecdsa::Signature::from_slice(signature)
+            .is_ok_and(|s| key.verify(signing_input, &s).is_ok()),
+        (alg, _) => {
+            return Err(verification_error(format!(
+                "unsupported signature algorithm {}",
+                alg
+            )))
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(verification_error("invalid signature"))
+    }
+}
+
+/// Verify that `certificate` has been issued by `issuer`
+fn verify_issued_by(certificate: &Certificate, issuer: &Certificate) -> Result<()> {
+    if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return Err(verification_error(format!(
+            "the certificate {} is not issued by {}",
+            certificate.tbs_certificate.subject, issuer.tbs_certificate.subject
+        )));
+    }
+
+    let tbs = certificate
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| verification_error(format!("cannot encode certificate: {}", e)))?;
+    let prehash = match certificate.signature_algorithm.oid.to_string().as_str() {
+        // ecdsa-with-SHA256
+        "1.2.840.10045.4.3.2" => sha2::Sha256::digest(&tbs).to_vec(),
+        // ecdsa-with-SHA384
+        "1.2.840.10045.4.3.3" => sha2::Sha384::digest(&tbs).to_vec(),
+        // ecdsa-with-SHA512
+        "1.2.840.10045.4.3.4" => sha2::Sha512::digest(&tbs).to_vec(),
+        oid => {
+            return Err(verification_error(format!(
+                "unsupported certificate signature algorithm {}",
+                oid
+            )))
+        }
+    };
+    let signature = certificate.signature.raw_bytes();
+    let valid = match public_key(issuer)? {
+        PublicKey::P256(key) => p256::ecdsa::Signature::from_der(signature)
+            .is_ok_and(|s| key.verify_prehash(&prehash, &s).is_ok()),
+        PublicKey::P384(key) => p384::ecdsa::Signature::from_der(signature)
+            .is_ok_and(|s| key.verify_prehash(&prehash, &s).is_ok()),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(verification_error(format!(
+            "invalid signature of certificate {}",
+            certificate.tbs_certificate.subject
+        )))
+    }
+}
+
+/// Read an extension of a certificate
+fn extension<'a, T: Decode<'a> + AssociatedOid>(certificate: &'a Certificate) -> Result<Option<T>> {
+    certificate
+        .tbs_certificate
+        .get::<T>()
+        .map(|e| e.map(|(_, extension)| extension))
+        .map_err(|e| {
+            verification_error(format!(
+                "invalid extension of certificate {}: {}",
+                certificate.tbs_certificate.subject, e
+            ))
+        })
+}
+
+/// Verify that the signing certificate may be used to sign code
+fn verify_signing_certificate(leaf: &Certificate) -> Result<()> {
+    let subject = &leaf.tbs_certificate.subject;
+    if extension::<BasicConstraints>(leaf)?.is_some_and(|bc| bc.ca) {
+        return Err(verification_error(format!(
+            "the signing certificate {} is a CA certificate",
+            subject
+        )));
+    }
+    if !extension::<KeyUsage>(leaf)?.is_some_and(|ku| ku.digital_signature()) {
+        return Err(verification_error(format!(
+            "the signing certificate {} is not valid for digital signatures",
+            subject
+        )));
+    }
+    if !extension::<ExtendedKeyUsage>(leaf)?.is_some_and(|eku| eku.0.contains(&ID_KP_CODE_SIGNING))
+    {
+        return Err(verification_error(format!(
+            "the signing certificate {} is not valid for code signing",
+            subject
+        )));
+    }
+    Ok(())
+}
+
+/// Verify that `issuer` may issue certificates, when it is followed by
+/// `intermediates` CA certificates in the chain
+fn verify_ca_certificate(issuer: &Certificate, intermediates: usize) -> Result<()> {
+    let subject = &issuer.tbs_certificate.subject;
+    let Some(constraints) = extension::<BasicConstraints>(issuer)?.filter(|bc| bc.ca) else {
+        return Err(verification_error(format!(
+            "the certificate {} is not a CA certificate",
+            subject
+        )));
+    };
+    if !extension::<KeyUsage>(issuer)?.is_some_and(|ku| ku.key_cert_sign()) {
+        return Err(verification_error(format!(
+            "the certificate {} is not valid for signing certificates",
+            subject
+        )));
+    }
+    if let Some(path_len) = constraints.path_len_constraint {
+        if usize::from(path_len) < intermediates {
+            return Err(verification_error(format!(
+                "the certificate chain exceeds the path length of {}",
+                subject
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Verify that the certificate chain leads to a root certificate of one of the
+/// trust stores of the policy
+fn verify_chain(
+    chain: &[Certificate],
+    policy: &TrustPolicy,
+    trust_store: &TrustStore,
+) -> Result<()> {
+    let (leaf, issuers) = chain
+        .split_first()
+        .ok_or_else(|| verification_error("the certificate chain is empty"))?;
+    verify_signing_certificate(leaf)?;
+    for (intermediates, issuer) in issuers.iter().enumerate() {
+        verify_ca_certificate(issuer, intermediates)?;
+    }
+    for pair in chain.windows(2) {
+        verify_issued_by(&pair[0], &pair[1])?;
+    }
+
+    let last = chain.last().unwrap_or(leaf);
+    let trusted = policy
+        .trust_stores
+        .iter()
+        .filter_map(|name| trust_store.certificates.get(name))
+        .flatten()
+        .any(|root| {
+            root == last
+                || (verify_ca_certificate(root, issuers.len()).is_ok()
+                    && verify_issued_by(last, root).is_ok())
+        });
+    if trusted {
+        Ok(())
+    } else {
+        Err(verification_error(
+            "the certificate chain doesn't lead to a trusted root certificate",
+        ))
+    }
+}
+
+/// Verify that the subject of the signing certificate matches one of the
+/// trusted identities of the policy. All the attributes of a trusted identity
+/// must be found in the subject.
+fn verify_identity(leaf: &Certificate, policy: &TrustPolicy) -> Result<()> {
+    let subject: HashSet<String> = leaf
+        .tbs_certificate
+        .subject
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .map(|atv| atv.to_string())
+        .collect();
+
+    for identity in &policy.trusted_identities {
+        if identity == "*" {
+            return Ok(());
+        }
+        let Some(name) = identity.strip_prefix(X509_SUBJECT_PREFIX) else {
+            continue;
+        };
+        let attributes = name
+            .split(',')
+            .map(|a| AttributeTypeAndValue::from_str(a.trim()).map(|atv| atv.to_string()))
+            .collect::<std::result::Result<Vec<_>, _>>();
+        match attributes {
+            Ok(attributes) if attributes.iter().all(|a| subject.contains(a)) => return Ok(()),
+            Ok(_) => {}
+            Err(e) => warn!(%identity, error = %e, "Ignoring invalid trusted identity"),
+        }
+    }
+
+    Err(verification_error(format!(
+        "the signing certificate {} is not a trusted identity",
+        leaf.tbs_certificate.subject
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use p256::ecdsa::{DerSignature, SigningKey};
+    use std::time::Duration;
+    use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+    use x509_cert::name::Name;
+    use x509_cert::serial_number::SerialNumber;
+    use x509_cert::spki::SubjectPublicKeyInfoOwned;
+    use x509_cert::time::Validity;
+
+    const DIGEST: &str = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const ROOT_NAME: &str = "CN=Root,O=Acme Rockets,C=US";
+    const SIGNER_NAME: &str = "CN=Signer,O=Acme Rockets,ST=WA,C=US";
+
+    struct Pki {
+        root: Certificate,
+        leaf: Certificate,
+        leaf_key: SigningKey,
+    }
+
+    fn certificate(
+        profile: Profile,
+        subject: &str,
+        key: &SigningKey,
+        issuer_key: &SigningKey,
+    ) -> Certificate {
+        let code_signing = matches!(profile, Profile::Leaf { .. });
+        let spki =
+            SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()).expect("public key info");
+        let mut builder = CertificateBuilder::new(
+            profile,
+            SerialNumber::from(1u32),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            Name::from_str(subject).unwrap(),
+            spki,
+            issuer_key,
+        )
+        .unwrap();
+        if code_signing {
+            builder
+                .add_extension(&ExtendedKeyUsage(vec![ID_KP_CODE_SIGNING]))
+                .unwrap();
+        }
+        builder.build::<DerSignature>().unwrap()
+    }
+
+    fn leaf_profile(issuer: &str) -> Profile {
+        Profile::Leaf {
+            issuer: Name::from_str(issuer).unwrap(),
+            enable_key_agreement: false,
+            enable_key_encipherment: false,
+        }
+    }
+
+    fn pki() -> Pki {
+        let root_key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let leaf_key = SigningKey::from_bytes(&[2; 32].into()).unwrap();
+        let root = certificate(Profile::Root, ROOT_NAME, &root_key, &root_key);
+        let leaf = certificate(leaf_profile(ROOT_NAME), SIGNER_NAME, &leaf_key, &root_key);
+        Pki {
+            root,
+            leaf,
+            leaf_key,
+        }
+    }
+
+    /// Sign a payload for `digest` with `key`, whose certificate is the first
+    /// of `chain`
+    fn sign(key: &SigningKey, chain: &[&Certificate], protected: &str, digest: &str) -> Vec<u8> {
+        let b64url = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let protected = b64url.encode(protected);
+        let payload = b64url.encode(format!(
+            r#"{{"targetArtifact":{{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"{}","size":42}}}}"#,
+            digest
+        ));
+        let signature: p256::ecdsa::Signature = p256::ecdsa::signature::Signer::sign(
+            key,
+            format!("{}.{}", protected, payload).as_bytes(),
+        );
+        let x5c: Vec<_> = chain
+            .iter()
+            .map(|c| b64.encode(c.to_der().unwrap()))
+            .collect();
+        serde_json::to_vec(&serde_json::json!({
+            "payload": payload,
+            "protected": protected,
+            "header": {"x5c": x5c},
+            "signature": b64url.encode(signature.to_bytes()),
+        }))
+        .unwrap()
+    }
+
+    fn protected_header() -> String {
+        format!(
+            r#"{{"alg":"ES256","crit":["io.cncf.notary.signingScheme"],"cty":"{}","io.cncf.notary.signingScheme":"notary.x509","io.cncf.notary.signingTime":"2023-01-01T00:00:00Z"}}"#,
+            NOTATION_PAYLOAD_CONTENT_TYPE
+        )
+    }
+
+    fn envelope(pki: &Pki, digest: &str) -> Vec<u8> {
+        sign(
+            &pki.leaf_key,
+            &[&pki.leaf, &pki.root],
+            &protected_header(),
+            digest,
+        )
+    }
+
+    fn policy(level: &str, identity: &str) -> TrustPolicy {
+        serde_json::from_value(serde_json::json!({
+            "name": "acme",
+            "registryScopes": ["registry.example.com/app"],
+            "signatureVerification": {"level": level},
+            "trustStores": ["ca:acme"],
+            "trustedIdentities": [identity],
+        }))
+        .unwrap()
+    }
+
+    fn trust_store(root: &Certificate) -> TrustStore {
+        let pem =
+            x509_cert::der::EncodePem::to_pem(root, x509_cert::der::pem::LineEnding::LF).unwrap();
+        TrustStore::new()
+            .with_certificates_pem("ca:acme", &pem)
+            .unwrap()
+    }
+
+    #[test]
+    fn verify_trusted_signature() {
+        let pki = pki();
+        let store = trust_store(&pki.root);
+        let envelope = envelope(&pki, DIGEST);
+
+        verify_envelope(
+            &policy("strict", "x509.subject: C=US, O=Acme Rockets, CN=Signer"),
+            &store,
+            &envelope,
+            DIGEST,
+            SystemTime::now(),
+        )
+        .expect("trusted signature");
+        verify_envelope(
+            &policy("strict", "*"),
+            &store,
+            &envelope,
+            DIGEST,
+            SystemTime::now(),
+        )
+        .expect("any identity");
+    }
+
+    #[test]
+    fn reject_untrusted_signatures() {
+        let pki = pki();
+        let envelope = envelope(&pki, DIGEST);
+        let policy = policy("strict", "*");
+
+        // Another image
+        assert!(verify_envelope(
+            &policy,
+            &trust_store(&pki.root),
+            &envelope,
+            "sha256:0000000000000000000000000000000000000000000000000000000000000000",
+            SystemTime::now(),
+        )
+        .is_err());
+
+        // Unknown root
+        assert!(verify_envelope(
+            &policy,
+            &trust_store(&pki.leaf),
+            &envelope,
+            DIGEST,
+            SystemTime::now()
+        )
+        .is_err());
+
+        // Untrusted identity
+        assert!(verify_envelope(
+            &super::test::policy("strict", "x509.subject: C=US, O=Other"),
+            &trust_store(&pki.root),
+            &envelope,
+            DIGEST,
+            SystemTime::now(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn expired_certificates() {
+        let pki = pki();
+        let store = trust_store(&pki.root);
+        let envelope = envelope(&pki, DIGEST);
+        let later = SystemTime::now() + Duration::from_secs(2 * 3600);
+
+        assert!(verify_envelope(&policy("strict", "*"), &store, &envelope, DIGEST, later).is_err());
+        verify_envelope(&policy("permissive", "*"), &store, &envelope, DIGEST, later)
+            .expect("expiry is only logged in permissive mode");
+    }
+
+    #[test]
+    fn certificate_usages() {
+        let pki = pki();
+        let store = trust_store(&pki.root);
+        let policy = policy("strict", "*");
+        let root_key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let key = SigningKey::from_bytes(&[3; 32].into()).unwrap();
+
+        // A signing certificate cannot issue certificates
+        let name = "CN=Other Signer,O=Acme Rockets,C=US";
+        let issued_by_leaf = certificate(leaf_profile(SIGNER_NAME), name, &key, &pki.leaf_key);
+        let envelope = sign(
+            &key,
+            &[&issued_by_leaf, &pki.leaf, &pki.root],
+            &protected_header(),
+            DIGEST,
+        );
+        assert!(verify_envelope(&policy, &store, &envelope, DIGEST, SystemTime::now()).is_err());
+
+        // The signing certificate must be valid for code signing
+        let spki = SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()).unwrap();
+        let without_eku = CertificateBuilder::new(
+            leaf_profile(ROOT_NAME),
+            SerialNumber::from(2u32),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            Name::from_str(name).unwrap(),
+            spki,
+            &root_key,
+        )
+        .unwrap()
+        .build::<DerSignature>()
+        .unwrap();
+        let envelope = sign(
+            &key,
+            &[&without_eku, &pki.root],
+            &protected_header(),
+            DIGEST,
+        );
+        assert!(verify_envelope(&policy, &store, &envelope, DIGEST, SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn path_length() {
+        let pki = pki();
+        let store = trust_store(&pki.root);
+        let policy = policy("strict", "*");
+        let root_key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let first_key = SigningKey::from_bytes(&[4; 32].into()).unwrap();
+        let second_key = SigningKey::from_bytes(&[5; 32].into()).unwrap();
+        let first_name = "CN=First CA,O=Acme Rockets,C=US";
+        let second_name = "CN=Second CA,O=Acme Rockets,C=US";
+        let sub_ca = |issuer: &str| Profile::SubCA {
+            issuer: Name::from_str(issuer).unwrap(),
+            path_len_constraint: Some(0),
+        };
+        let first = certificate(sub_ca(ROOT_NAME), first_name, &first_key, &root_key);
+        let second = certificate(sub_ca(first_name), second_name, &second_key, &first_key);
+
+        let leaf = certificate(
+            leaf_profile(first_name),
+            SIGNER_NAME,
+            &pki.leaf_key,
+            &first_key,
+        );
+        let envelope = sign(
+            &pki.leaf_key,
+            &[&leaf, &first, &pki.root],
+            &protected_header(),
+            DIGEST,
+        );
+        verify_envelope(&policy, &store, &envelope, DIGEST, SystemTime::now())
+            .expect("one intermediate certificate");
+
+        // The first intermediate certificate cannot be followed by another one
+        let leaf = certificate(
+            leaf_profile(second_name),
+            SIGNER_NAME,
+            &pki.leaf_key,
+            &second_key,
+        );
+        let envelope = sign(
+            &pki.leaf_key,
+            &[&leaf, &second, &first, &pki.root],
+            &protected_header(),
+            DIGEST,
+        );
+        assert!(verify_envelope(&policy, &store, &envelope, DIGEST, SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn critical_headers() {
+        let pki = pki();
+        let store = trust_store(&pki.root);
+        let policy = policy("strict", "*");
+        let verify = |protected: &str| {
+            let envelope = sign(&pki.leaf_key, &[&pki.leaf, &pki.root], protected, DIGEST);
+            verify_envelope(&policy, &store, &envelope, DIGEST, SystemTime::now())
+        };
+        let header = |crit: &str, extra: &str| {
+            format!(
+                r#"{{"alg":"ES256","crit":{},"cty":"{}","io.cncf.notary.signingScheme":"notary.x509"{}}}"#,
+                crit, NOTATION_PAYLOAD_CONTENT_TYPE, extra
+            )
+        };
+        let expiry = r#","io.cncf.notary.expiry":"2999-01-01T00:00:00Z""#;
+
+        verify(&header(
+            r#"["io.cncf.notary.signingScheme","io.cncf.notary.expiry"]"#,
+            expiry,
+        ))
+        .expect("known critical headers");
+        assert!(verify(&header(
+            r#"["io.cncf.notary.signingScheme","io.cncf.notary.verificationPlugin"]"#,
+            r#","io.cncf.notary.verificationPlugin":"plugin""#,
+        ))
+        .is_err());
+        assert!(verify(&header("[]", "")).is_err());
+        assert!(verify(&header(r#"["io.cncf.notary.signingScheme"]"#, expiry)).is_err());
+    }
+
+    #[tokio::test]
+    async fn pull_verified_digest() {
+        let layer = b"hello";
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"{}","digest":"{}","size":2}},"layers":[{{"mediaType":"{}","digest":"{}","size":{}}}]}}"#,
+            crate::manifest::OCI_IMAGE_MEDIA_TYPE,
+            crate::manifest::IMAGE_CONFIG_MEDIA_TYPE,
+            crate::sha256_digest(b"{}"),
+            crate::manifest::IMAGE_LAYER_MEDIA_TYPE,
+            crate::sha256_digest(layer),
+            layer.len()
+        );
+        let digest = crate::sha256_digest(manifest.as_bytes());
+        let (addr, server) = crate::client::test::serve_http(vec![
+            (200, Vec::new()),
+            // The registry doesn't return the digest of the tag
+            (200, Vec::new()),
+            (200, manifest.clone().into_bytes()),
+            // The signatures can't be listed
+            (500, Vec::new()),
+            (200, manifest.into_bytes()),
+            (200, b"{}".to_vec()),
+            (200, layer.to_vec()),
+        ]);
+
+        let pki = pki();
+        let document = serde_json::from_value(serde_json::json!({
+            "version": "1.0",
+            "trustPolicies": [{
+                "name": "app",
+                "registryScopes": [format!("{}/app", addr)],
+                "signatureVerification": {"level": "audit"},
+                "trustStores": ["ca:acme"],
+                "trustedIdentities": ["*"],
+            }],
+        }))
+        .unwrap();
+        let mut client = Client::try_from(crate::client::ClientConfig {
+            protocol: crate::client::ClientProtocol::Http,
+            retry: crate::retry::RetryPolicy::disabled(),
+            notation_verifier: Some(std::sync::Arc::new(NotationVerifier::new(
+                document,
+                trust_store(&pki.root),
+            ))),
+            ..Default::default()
+        })
+        .unwrap();
+        let image = Reference::try_from(format!("{}/app:v1", addr)).unwrap();
+        let pulled = client
+            .pull(
+                &image,
+                &RegistryAuth::Anonymous,
+                vec![crate::manifest::IMAGE_LAYER_MEDIA_TYPE],
+            )
+            .await
+            .expect("unsigned images are accepted in audit mode");
+        assert_eq!(pulled.digest.as_deref(), Some(digest.as_str()));
+
+        // The manifest is pulled by the digest that was verified
+        let requests = server.join().unwrap();
+        assert!(
+            requests[4].starts_with(&format!("get /v2/app/manifests/{} ", digest)),
+            "{}",
+            requests[4]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_cached_images() {
+        let layer = b"hello";
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"{}","digest":"{}","size":2}},"layers":[{{"mediaType":"{}","digest":"{}","size":{}}}]}}"#,
+            crate::manifest::OCI_IMAGE_MEDIA_TYPE,
+            crate::manifest::IMAGE_CONFIG_MEDIA_TYPE,
+            crate::sha256_digest(b"{}"),
+            crate::manifest::IMAGE_LAYER_MEDIA_TYPE,
+            crate::sha256_digest(layer),
+            layer.len()
+        );
+        let digest = crate::sha256_digest(manifest.as_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let cache = std::sync::Arc::new(crate::cache::ImageCache::new(dir.path()).unwrap());
+        cache.insert(&digest, manifest.as_bytes()).unwrap();
+        cache.insert(&crate::sha256_digest(b"{}"), b"{}").unwrap();
+        cache.insert(&crate::sha256_digest(layer), layer).unwrap();
+        let image: Reference = "registry.example.com/app:v1".parse().unwrap();
+        cache.set_reference(&image.whole(), &digest).unwrap();
+
+        let pki = pki();
+        let document = serde_json::from_value(serde_json::json!({
+            "version": "1.0",
+            "trustPolicies": [{
+                "name": "app",
+                "registryScopes": ["registry.example.com/app"],
+                "signatureVerification": {"level": "strict"},
+                "trustStores": ["ca:acme"],
+                "trustedIdentities": ["*"],
+            }],
+        }))
+        .unwrap();
+        let mut client = Client::try_from(crate::client::ClientConfig {
+            image_cache: Some(cache.clone()),
+            pull_policy: crate::client::PullPolicy::Never,
+            notation_verifier: Some(std::sync::Arc::new(NotationVerifier::new(
+                document,
+                trust_store(&pki.root),
+            ))),
+            ..Default::default()
+        })
+        .unwrap();
+        let media_types = vec![crate::manifest::IMAGE_LAYER_MEDIA_TYPE];
+
+        // The signature of the image was never verified
+        assert!(matches!(
+            client
+                .pull(&image, &RegistryAuth::Anonymous, media_types.clone())
+                .await,
+            Err(OciDistributionError::SignatureVerificationError(_))
+        ));
+
+        cache.set_verified(&digest).unwrap();
+        let pulled = client
+            .pull(&image, &RegistryAuth::Anonymous, media_types)
+            .await
+            .expect("verified cached image");
+        assert_eq!(pulled.digest.as_deref(), Some(digest.as_str()));
+    }
+
+    #[test]
+    fn select_policy() {
+        let document: TrustPolicyDocument = serde_json::from_str(
+            r#"{
+                "version": "1.0",
+                "trustPolicies": [
+                    {
+                        "name": "default",
+                        "registryScopes": ["*"],
+                        "signatureVerification": {"level": "audit"}
+                    },
+                    {
+                        "name": "app",
+                        "registryScopes": ["registry.example.com/app"],
+                        "signatureVerification": {"level": "strict"},
+                        "trustStores": ["ca:acme"],
+                        "trustedIdentities": ["*"]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let app: Reference = "registry.example.com/app:v1".parse().unwrap();
+        let other: Reference = "registry.example.com/other:v1".parse().unwrap();
+        assert_eq!(document.policy_for(&app).unwrap().name, "app");
+        assert_eq!(document.policy_for(&other).unwrap().name, "default");
+        assert_eq!(
+            document
+                .policy_for(&other)
+                .unwrap()
+                .signature_verification
+                .level,
+            VerificationLevel::Audit
+        );
+    }
+}
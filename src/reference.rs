@@ -22,10 +22,14 @@ pub enum ParseError {
     DigestInvalidLength,
     /// Unsupported digest algorithm
     DigestUnsupported,
+    /// Invalid domain format
+    DomainInvalidFormat,
     /// Repository name must be lowercase
     NameContainsUppercase,
     /// Repository name must have at least one component
     NameEmpty,
+    /// Invalid repository name component format
+    NameInvalidFormat,
     /// Repository name must not be more than NAME_TOTAL_LENGTH_MAX characters
     NameTooLong,
     /// Invalid reference format
@@ -40,8 +44,10 @@ impl fmt::Display for ParseError {
             ParseError::DigestInvalidFormat => write!(f, "invalid checksum digest format"),
             ParseError::DigestInvalidLength => write!(f, "invalid checksum digest length"),
             ParseError::DigestUnsupported => write!(f, "unsupported digest algorithm"),
+            ParseError::DomainInvalidFormat => write!(f, "invalid domain format"),
             ParseError::NameContainsUppercase => write!(f, "repository name must be lowercase"),
             ParseError::NameEmpty => write!(f, "repository name must have at least one component"),
+            ParseError::NameInvalidFormat => write!(f, "invalid repository name component format"),
             ParseError::NameTooLong => write!(
                 f,
                 "repository name must not be more than {} characters",
@@ -72,6 +78,20 @@ impl Error for ParseError {}
 /// assert_eq!(Some("latest"), reference.tag());
 /// assert_eq!(None, reference.digest());
 /// ```
+///
+/// Short names are normalized the way docker does: images without a domain
+/// belong to `docker.io`, and the official images of Docker Hub to its
+/// `library` namespace. A reference without a tag nor a digest gets the
+/// `latest` tag. The familiar form is the short one:
+///
+/// ```
+/// use oci_distribution::Reference;
+///
+/// let reference: Reference = "nginx".parse().unwrap();
+///
+/// assert_eq!("docker.io/library/nginx:latest", reference.to_string());
+/// assert_eq!("nginx:latest", reference.familiar());
+/// ```
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
 pub struct Reference {
     registry: String,
@@ -106,11 +126,7 @@ impl Reference {
     /// Some registries, such as docker.io, uses a different address for the actual
     /// registry. This function implements such redirection.
     pub fn resolve_registry(&self) -> &str {
-        let registry = self.registry();
-        match registry {
-            "docker.io" => "index.docker.io",
-            _ => registry,
-        }
+        resolve_registry(self.registry())
     }
 
     /// registry returns the name of the registry.
@@ -142,7 +158,35 @@ impl Reference {
         }
     }
 
+    /// familiar returns the whole reference, in the short form used by the
+    /// docker CLI: the `docker.io` domain and the `library` namespace of
+    /// Docker Hub are left out.
+    pub fn familiar(&self) -> String {
+        let mut s = match self.registry() {
+            DOCKER_HUB_DOMAIN => self
+                .repository()
+                .strip_prefix(DOCKER_HUB_OFFICIAL_REPO_NAME)
+                .and_then(|r| r.strip_prefix('/'))
+                .filter(|r| !r.contains('/'))
+                .unwrap_or(self.repository())
+                .to_string(),
+            _ => self.full_name(),
+        };
+        if let Some(t) = self.tag() {
+            s.push(':');
+            s.push_str(t);
+        }
+        if let Some(d) = self.digest() {
+            s.push('@');
+            s.push_str(d);
+        }
+        s
+    }
+
     /// whole returns the whole reference.
+    ///
+    /// It is also the output of `Display`, and parses back into the same
+    /// reference.
     pub fn whole(&self) -> String {
         let mut s = self.full_name();
         if let Some(t) = self.tag() {
@@ -182,63 +226,42 @@ impl TryFrom<String> for Reference {
         if s.is_empty() {
             return Err(ParseError::NameEmpty);
         }
-        lazy_static! {
-            static ref RE: regex::Regex = regexp::must_compile(regexp::REFERENCE_REGEXP);
+        let (name, digest) = match s.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (s.as_str(), None),
         };
-        let captures = match RE.captures(&s) {
-            Some(caps) => caps,
-            None => {
-                return Err(ParseError::ReferenceInvalidFormat);
-            }
+        // The tag follows the last colon, unless it's part of the domain
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((n, t)) if !t.contains('/') => (n, Some(t)),
+            _ => (name, None),
         };
-        let name = &captures[1];
-        let mut tag = captures.get(2).map(|m| m.as_str().to_owned());
-        let digest = captures.get(3).map(|m| m.as_str().to_owned());
-        if tag.is_none() && digest.is_none() {
-            tag = Some(DEFAULT_TAG.into());
+        if name.is_empty() {
+            return Err(ParseError::ReferenceInvalidFormat);
+        }
+        if name.len() > NAME_TOTAL_LENGTH_MAX {
+            return Err(ParseError::NameTooLong);
         }
+
         let (registry, repository) = split_domain(name);
-        let reference = Reference {
+        validate_domain(&registry)?;
+        validate_repository(&repository)?;
+        if let Some(tag) = tag {
+            validate_tag(tag)?;
+        }
+        if let Some(digest) = digest {
+            validate_digest(digest)?;
+        }
+
+        let tag = match (tag, digest) {
+            (None, None) => Some(DEFAULT_TAG.to_string()),
+            (tag, _) => tag.map(str::to_string),
+        };
+        Ok(Reference {
             registry,
             repository,
             tag,
-            digest,
-        };
-        if reference.repository().len() > NAME_TOTAL_LENGTH_MAX {
-            return Err(ParseError::NameTooLong);
-        }
-        // Digests much always be hex-encoded, ensuring that their hex portion will always be
-        // size*2
-        if reference.digest().is_some() {
-            let d = reference.digest().unwrap();
-            // FIXME: we should actually separate the algorithm from the digest
-            // using regular expressions. This won't hold up if we support an
-            // algorithm more or less than 6 characters like sha1024.
-            if d.len() < 8 {
-                return Err(ParseError::DigestInvalidFormat);
-            }
-            let algo = &d[0..6];
-            let digest = &d[7..];
-            match algo {
-                "sha256" => {
-                    if digest.len() != 64 {
-                        return Err(ParseError::DigestInvalidLength);
-                    }
-                }
-                "sha384" => {
-                    if digest.len() != 96 {
-                        return Err(ParseError::DigestInvalidLength);
-                    }
-                }
-                "sha512" => {
-                    if digest.len() != 128 {
-                        return Err(ParseError::DigestInvalidLength);
-                    }
-                }
-                _ => return Err(ParseError::DigestUnsupported),
-            }
-        }
-        Ok(reference)
+            digest: digest.map(str::to_string),
+        })
     }
 }
 
@@ -255,6 +278,15 @@ impl From<Reference> for String {
     }
 }
 
+/// Return the address of the registry serving the content of `registry`,
+/// such as `index.docker.io` for `docker.io`
+pub(crate) fn resolve_registry(registry: &str) -> &str {
+    match registry {
+        "docker.io" => "index.docker.io",
+        _ => registry,
+    }
+}
+
 /// Splits a repository name to domain and remotename string.
 /// If no valid domain is found, the default domain is used. Repository name
 /// needs to be already validated before.
@@ -290,6 +322,71 @@ fn split_domain(name: &str) -> (String, String) {
     (domain, remainder)
 }
 
+fn validate_domain(domain: &str) -> Result<(), ParseError> {
+    lazy_static! {
+        static ref RE: regex::Regex = regexp::must_compile(regexp::DOMAIN_REGEXP);
+    };
+    if !RE.is_match(domain) {
+        return Err(ParseError::DomainInvalidFormat);
+    }
+    Ok(())
+}
+
+fn validate_repository(repository: &str) -> Result<(), ParseError> {
+    lazy_static! {
+        static ref RE: regex::Regex = regexp::must_compile(regexp::PATH_COMPONENT_REGEXP);
+    };
+    for component in repository.split('/') {
+        if RE.is_match(component) {
+            continue;
+        }
+        if component.is_empty() {
+            return Err(ParseError::ReferenceInvalidFormat);
+        }
+        if RE.is_match(&component.to_lowercase()) {
+            return Err(ParseError::NameContainsUppercase);
+        }
+        return Err(ParseError::NameInvalidFormat);
+    }
+    Ok(())
+}
+
+fn validate_tag(tag: &str) -> Result<(), ParseError> {
+    lazy_static! {
+        static ref RE: regex::Regex = regexp::must_compile(regexp::TAG_REGEXP);
+    };
+    if !RE.is_match(tag) {
+        return Err(ParseError::TagInvalidFormat);
+    }
+    Ok(())
+}
+
+/// Digests must always be hex-encoded, ensuring that their hex portion will
+/// always be size*2
+fn validate_digest(digest: &str) -> Result<(), ParseError> {
+    lazy_static! {
+        static ref RE: regex::Regex = regexp::must_compile(regexp::DIGEST_REGEXP);
+    };
+    let captures = RE.captures(digest).ok_or(ParseError::DigestInvalidFormat)?;
+    let encoded = &captures[2];
+    let len = match &captures[1] {
+        "sha256" => 64,
+        "sha384" => 96,
+        "sha512" => 128,
+        _ => return Err(ParseError::DigestUnsupported),
+    };
+    if !encoded
+        .bytes()
+        .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return Err(ParseError::DigestInvalidFormat);
+    }
+    if encoded.len() != len {
+        return Err(ParseError::DigestInvalidLength);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -318,6 +415,12 @@ mod test {
             case("foo_bar.com:8080", "docker.io", "library/foo_bar.com", Some("8080"), None, "docker.io/library/foo_bar.com:8080" ),
             case("foo/foo_bar.com:8080", "docker.io", "foo/foo_bar.com", Some("8080"), None, "docker.io/foo/foo_bar.com:8080"),
             case("opensuse/leap:15.3", "docker.io", "opensuse/leap", Some("15.3"), None, "docker.io/opensuse/leap:15.3"),
+            case("nginx:latest", "docker.io", "library/nginx", Some("latest"), None, "docker.io/library/nginx:latest"),
+            case("docker.io/nginx", "docker.io", "library/nginx", Some("latest"), None, "docker.io/library/nginx:latest"),
+            case("index.docker.io/library/nginx:1.25", "docker.io", "library/nginx", Some("1.25"), None, "docker.io/library/nginx:1.25"),
+            case("localhost/repo", "localhost", "repo", Some("latest"), None, "localhost/repo:latest"),
+            case("[::1]:5000/repo:tag", "[::1]:5000", "repo", Some("tag"), None, "[::1]:5000/repo:tag"),
+            case("nginx@sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff", "docker.io", "library/nginx", None, Some("sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"), "docker.io/library/nginx@sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"),
         )]
         fn parse_good_reference(
             input: &str,
@@ -335,6 +438,29 @@ mod test {
             assert_eq!(tag, reference.tag());
             assert_eq!(digest, reference.digest());
             assert_eq!(whole, reference.whole());
+
+            // Both the whole and the familiar forms parse back into the same
+            // reference
+            assert_eq!(whole, reference.to_string());
+            assert_eq!(
+                Reference::try_from(reference.to_string()),
+                Ok(reference.clone())
+            );
+            assert_eq!(Reference::try_from(reference.familiar()), Ok(reference));
+        }
+
+        #[rstest(
+            input,
+            familiar,
+            case("nginx", "nginx:latest"),
+            case("docker.io/library/nginx:1.25", "nginx:1.25"),
+            case("docker.io/library/nginx/unit:1.25", "library/nginx/unit:1.25"),
+            case("opensuse/leap:15.3", "opensuse/leap:15.3"),
+            case("ghcr.io/library/nginx:latest", "ghcr.io/library/nginx:latest")
+        )]
+        fn familiar_reference(input: &str, familiar: &str) {
+            let reference = Reference::try_from(input).expect("could not parse reference");
+            assert_eq!(familiar, reference.familiar());
         }
 
         #[rstest(input, err,
@@ -343,15 +469,18 @@ mod test {
             case("@sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff", ParseError::ReferenceInvalidFormat),
             case("repo@sha256:ffffffffffffffffffffffffffffffffff", ParseError::DigestInvalidLength),
             case("validname@invaliddigest:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff", ParseError::DigestUnsupported),
-            // FIXME: should really pass a ParseError::NameContainsUppercase, but "invalid format" is good enough for now.
-            case("Uppercase:tag", ParseError::ReferenceInvalidFormat),
-            // FIXME: "Uppercase" is incorrectly handled as a domain-name here, and therefore passes.
-            // https://github.com/docker/distribution/blob/master/reference/reference_test.go#L104-L109
-            // case("Uppercase/lowercase:tag", ParseError::NameContainsUppercase),
-            // FIXME: should really pass a ParseError::NameContainsUppercase, but "invalid format" is good enough for now.
-            case("test:5000/Uppercase/lowercase:tag", ParseError::ReferenceInvalidFormat),
+            case("repo@sha256:FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", ParseError::DigestInvalidFormat),
+            case("repo@sha256", ParseError::DigestInvalidFormat),
+            case("Uppercase:tag", ParseError::NameContainsUppercase),
+            case("Uppercase/lowercase:tag", ParseError::NameContainsUppercase),
+            case("test:5000/Uppercase/lowercase:tag", ParseError::NameContainsUppercase),
+            case("test..com/repo", ParseError::DomainInvalidFormat),
+            case("-test.com/repo", ParseError::DomainInvalidFormat),
+            case("repo:-tag", ParseError::TagInvalidFormat),
+            case("repo:tag!", ParseError::TagInvalidFormat),
+            case("test.com/repo//name", ParseError::ReferenceInvalidFormat),
             case("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", ParseError::NameTooLong),
-            case("aa/asdf$$^/aa", ParseError::ReferenceInvalidFormat)
+            case("aa/asdf$$^/aa", ParseError::NameInvalidFormat)
         )]
         fn parse_bad_reference(input: &str, err: ParseError) {
             assert_eq!(Reference::try_from(input).unwrap_err(), err)
@@ -1,13 +1,22 @@
 use regex::{Regex, RegexBuilder};
 
-/// REFERENCE_REGEXP is the full supported format of a reference. The regexp
-// is anchored and has capturing groups for name, tag, and digest components.
-pub const REFERENCE_REGEXP: &str = r"^((?:(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])(?:(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))+)?(?::[0-9]+)?/)?[a-z0-9]+(?:(?:(?:[._]|__|[-]*)[a-z0-9]+)+)?(?:(?:/[a-z0-9]+(?:(?:(?:[._]|__|[-]*)[a-z0-9]+)+)?)+)?)(?::([\w][\w.-]{0,127}))?(?:@([A-Za-z][A-Za-z0-9]*(?:[-_+.][A-Za-z][A-Za-z0-9]*)*[:][[:xdigit:]]{32,}))?$";
+/// DOMAIN_REGEXP matches the domain of a reference: a host name, an IPv4
+/// address or a bracketed IPv6 address, optionally followed by a port. It is
+/// anchored.
+pub const DOMAIN_REGEXP: &str = r"^(?:(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))*|\[[a-fA-F0-9:]+\])(?::[0-9]+)?$";
 
-/// ANCHORED_NAME_REGEXP is used to parse a name value, capturing the domain and
-/// trailing components.
-#[allow(dead_code)]
-pub const ANCHORED_NAME_REGEXP: &str = r"^(?:((?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])(?:(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))+)?(?::[0-9]+)?)/)?([a-z0-9]+(?:(?:(?:[._]|__|[-]*)[a-z0-9]+)+)?(?:(?:/[a-z0-9]+(?:(?:(?:[._]|__|[-]*)[a-z0-9]+)+)?)+)?)$";
+/// PATH_COMPONENT_REGEXP matches a single component of a repository name,
+/// lowercase alphanumerics separated by periods, one or two underscores, or
+/// dashes. It is anchored.
+pub const PATH_COMPONENT_REGEXP: &str = r"^[a-z0-9]+(?:(?:[._]|__|[-]+)[a-z0-9]+)*$";
+
+/// TAG_REGEXP matches a tag: up to 128 word characters, periods and dashes,
+/// not starting with a period or a dash. It is anchored.
+pub const TAG_REGEXP: &str = r"^[\w][\w.-]{0,127}$";
+
+/// DIGEST_REGEXP matches a digest, capturing its algorithm and its encoded
+/// part. It is anchored.
+pub const DIGEST_REGEXP: &str = r"^([a-z0-9]+(?:[+._-][a-z0-9]+)*):([a-zA-Z0-9=_-]+)$";
 
 pub fn must_compile(r: &str) -> Regex {
     RegexBuilder::new(r)
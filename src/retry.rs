@@ -0,0 +1,252 @@
+//! Retries of the requests failing because of transient errors
+//!
+//! Registries, and the storage backends they redirect blob downloads to,
+//! sometimes fail with server errors or drop connections. Instead of failing
+//! the whole pull, the token, manifest and blob requests that fail this way
+//! are retried according to the [`RetryPolicy`] of
+//! [`ClientConfig::retry`](crate::client::ClientConfig::retry), with an
+//! exponential backoff and jitter.
+//!
+//! Only sending the requests is retried: a blob whose download fails halfway
+//! through isn't pulled again, since part of it might already have been handed
+//! over to the caller.
+//!
+//! Requests rejected with `429 Too Many Requests`, such as when the pull rate
+//! limit of Docker Hub is reached, are retried after the delay of their
+//! `Retry-After` header. When it's too far away, the request fails with
+//! [`OciDistributionError::RateLimitedError`](crate::errors::OciDistributionError::RateLimitedError).
+//! The rate limits advertised by the registries are recorded, and can be
+//! inspected with [`Client::rate_limit`](crate::Client::rate_limit).
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+/// How the requests failing because of transient errors are retried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of retries of a request. Zero disables retries.
+    /// Defaults to 3.
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles with every retry.
+    /// Defaults to 200 milliseconds.
+    pub initial_backoff: Duration,
+    /// The maximum delay between two attempts. Defaults to 10 seconds.
+    pub max_backoff: Duration,
+    /// Requests aren't retried anymore once this much time has elapsed since
+    /// their first attempt. Defaults to 30 seconds.
+    pub max_elapsed_time: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+            max_elapsed_time: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries requests
+    pub fn disabled() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before the given retry, counting from zero.
+    ///
+    /// The delay is picked at random between half of the exponential backoff
+    /// and the full backoff, so that clients failing at the same time don't
+    /// retry in lockstep.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        backoff / 2 + (backoff / 2).mul_f64(random_fraction())
+    }
+}
+
+/// Return a random number between 0 and 1
+fn random_fraction() -> f64 {
+    // Every `RandomState` is seeded differently
+    let random = RandomState::new().build_hasher().finish();
+    random as f64 / u64::MAX as f64
+}
+
+/// The rate limit of a registry, as advertised by the `RateLimit-Limit` and
+/// `RateLimit-Remaining` headers of its responses, such as `100;w=21600`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The number of requests allowed during the window
+    pub limit: Option<u64>,
+    /// The number of requests left during the current window
+    pub remaining: Option<u64>,
+    /// The duration of the window
+    pub window: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Parse the rate limit headers of a response, returning `None` when
+    /// there are none
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let parse = |name: &str| -> Option<(u64, Option<Duration>)> {
+            let value = headers.get(name)?.to_str().ok()?;
+            let mut parts = value.split(';').map(str::trim);
+            let quota = parts.next()?.parse().ok()?;
+            let window = parts
+                .find_map(|p| p.strip_prefix("w="))
+                .and_then(|w| w.parse().ok())
+                .map(Duration::from_secs);
+            Some((quota, window))
+        };
+        let limit = parse("ratelimit-limit");
+        let remaining = parse("ratelimit-remaining");
+        if limit.is_none() && remaining.is_none() {
+            return None;
+        }
+        Some(RateLimit {
+            limit: limit.map(|(quota, _)| quota),
+            remaining: remaining.map(|(quota, _)| quota),
+            window: limit.or(remaining).and_then(|(_, window)| window),
+        })
+    }
+}
+
+/// Return the delay of the `Retry-After` header of a response, given either
+/// as a number of seconds or as a date
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Return `true` for the server errors that are worth retrying
+pub(crate) fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        && status != StatusCode::NOT_IMPLEMENTED
+        && status != StatusCode::HTTP_VERSION_NOT_SUPPORTED
+}
+
+/// Return `true` for the errors caused by timeouts and dropped connections
+pub(crate) fn is_transient_error(error: &reqwest::Error) -> bool {
+    if error.is_timeout() || error.is_connect() {
+        return true;
+    }
+    let mut source = error.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+                    | std::io::ErrorKind::TimedOut
+            ) {
+                return true;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff_with_jitter() {
+        let policy = RetryPolicy::default();
+        for (retry, max) in [(0, 200), (1, 400), (2, 800), (10, 10_000), (40, 10_000)] {
+            let max = Duration::from_millis(max);
+            for _ in 0..10 {
+                let backoff = policy.backoff(retry);
+                assert!(backoff >= max / 2 && backoff <= max, "{:?}", backoff);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(RateLimit::from_headers(&headers), None);
+
+        headers.insert("ratelimit-limit", "100;w=21600".parse().unwrap());
+        headers.insert("ratelimit-remaining", "76;w=21600".parse().unwrap());
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            Some(RateLimit {
+                limit: Some(100),
+                remaining: Some(76),
+                window: Some(Duration::from_secs(21600)),
+            })
+        );
+
+        headers.remove("ratelimit-limit");
+        headers.insert("ratelimit-remaining", "0".parse().unwrap());
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            Some(RateLimit {
+                limit: None,
+                remaining: Some(0),
+                window: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(3600)).to_rfc2822();
+        headers.insert(reqwest::header::RETRY_AFTER, date.parse().unwrap());
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(3500) && delay <= Duration::from_secs(3600));
+
+        headers.insert(reqwest::header::RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn transient_status() {
+        assert!(is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_transient_status(StatusCode::BAD_GATEWAY));
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient_status(StatusCode::GATEWAY_TIMEOUT));
+        assert!(!is_transient_status(StatusCode::NOT_IMPLEMENTED));
+        assert!(!is_transient_status(StatusCode::NOT_FOUND));
+        assert!(!is_transient_status(StatusCode::OK));
+    }
+}
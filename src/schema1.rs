@@ -0,0 +1,289 @@
+//! Compatibility with docker v2 schema 1 manifests
+//!
+//! Some older registries and mirrors still serve [schema 1
+//! manifests](https://github.com/distribution/distribution/blob/main/docs/content/spec/deprecated-schema-v1.md),
+//! which are signed with a JSON web signature and don't reference a config
+//! blob. They are converted into docker v2 schema 2 image manifests, whose
+//! config is built out of the history of the schema 1 manifest, and embedded in
+//! the `data` of the config descriptor.
+//!
+//! The signatures are stripped without being verified: they rely on libtrust
+//! keys, which have long been deprecated. The digest of a schema 1 manifest is
+//! the digest of its payload, once the signatures have been stripped.
+
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::errors::{OciDistributionError, Result};
+use crate::manifest::{
+    OciDescriptor, OciImageManifest, IMAGE_DOCKER_CONFIG_MEDIA_TYPE,
+    IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE,
+};
+use crate::sha256_digest;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Schema1Manifest {
+    #[serde(default)]
+    architecture: String,
+    fs_layers: Vec<FsLayer>,
+    history: Vec<History>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FsLayer {
+    blob_sum: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct History {
+    v1_compatibility: String,
+}
+
+#[derive(Deserialize)]
+struct Signatures {
+    #[serde(default)]
+    signatures: Vec<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    protected: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Protected {
+    format_length: usize,
+    format_tail: String,
+}
+
+fn parsing_error(message: impl std::fmt::Display) -> OciDistributionError {
+    OciDistributionError::ManifestParsingError(format!("schema 1 manifest: {}", message))
+}
+
+/// Return the payload of a schema 1 manifest, without its signatures.
+/// Unsigned manifests are returned as is.
+///
+/// The protected header of the signatures records the length of the payload
+/// before the signatures, and the tail that follows them, so that the payload
+/// can be recovered byte for byte.
+pub(crate) fn strip_signatures(raw: &[u8]) -> Result<Vec<u8>> {
+    let signed: Signatures = serde_json::from_slice(raw).map_err(parsing_error)?;
+    let Some(signature) = signed.signatures.first() else {
+        return Ok(raw.to_vec());
+    };
+
+    let b64url = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let protected = b64url
+        .decode(signature.protected.trim_end_matches('='))
+        .map_err(parsing_error)?;
+    let protected: Protected = serde_json::from_slice(&protected).map_err(parsing_error)?;
+    let tail = b64url
+        .decode(protected.format_tail.trim_end_matches('='))
+        .map_err(parsing_error)?;
+
+    let mut payload = raw
+        .get(..protected.format_length)
+        .ok_or_else(|| parsing_error("invalid signature format length"))?
+        .to_vec();
+    payload.extend(tail);
+    serde_json::from_slice::<Value>(&payload).map_err(parsing_error)?;
+    Ok(payload)
+}
+
+/// Convert the payload of a schema 1 manifest into an image manifest.
+///
+/// The layers whose history is marked as `throwaway`, which are empty, are
+/// dropped. Since the layers aren't downloaded, their size is unknown and
+/// left to zero, and the `rootfs` of the config doesn't list their diff IDs.
+pub(crate) fn convert(payload: &[u8]) -> Result<OciImageManifest> {
+    let manifest: Schema1Manifest = serde_json::from_slice(payload).map_err(parsing_error)?;
+    if manifest.fs_layers.len() != manifest.history.len() {
+        return Err(parsing_error("mismatched fsLayers and history lengths"));
+    }
+    if manifest.history.is_empty() {
+        return Err(parsing_error("no layers"));
+    }
+
+    let mut layers = Vec::new();
+    let mut history = Vec::new();
+    // Schema 1 manifests list the most recent layer first
+    for (layer, h) in manifest.fs_layers.iter().zip(&manifest.history).rev() {
+        let v1: Map<String, Value> =
+            serde_json::from_str(&h.v1_compatibility).map_err(parsing_error)?;
+        let empty_layer = v1.get("throwaway").and_then(Value::as_bool) == Some(true);
+
+        let mut entry = Map::new();
+        for key in ["created", "author", "comment"] {
+            if let Some(value) = v1.get(key) {
+                entry.insert(key.to_string(), value.clone());
+            }
+        }
+        let created_by = v1
+            .get("container_config")
+            .and_then(|c| c.get("Cmd"))
+            .and_then(Value::as_array)
+            .map(|cmd| {
+                cmd.iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            });
+        if let Some(created_by) = created_by {
+            entry.insert("created_by".to_string(), created_by.into());
+        }
+        if empty_layer {
+            entry.insert("empty_layer".to_string(), true.into());
+        } else {
+            layers.push(OciDescriptor {
+                media_type: IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE.to_string(),
+                digest: layer.blob_sum.clone(),
+                ..Default::default()
+            });
+        }
+        history.push(Value::Object(entry));
+    }
+
+    // The config of the image is the one of its most recent layer
+    let mut config: Map<String, Value> =
+        serde_json::from_str(&manifest.history[0].v1_compatibility).map_err(parsing_error)?;
+    for key in ["id", "parent", "parent_id", "layer_id", "throwaway", "Size"] {
+        config.remove(key);
+    }
+    if !manifest.architecture.is_empty() {
+        config
+            .entry("architecture")
+            .or_insert_with(|| manifest.architecture.clone().into());
+    }
+    config.insert("history".to_string(), Value::Array(history));
+    config.insert(
+        "rootfs".to_string(),
+        json!({ "type": "layers", "diff_ids": [] }),
+    );
+    let config = serde_json::to_vec(&config)?;
+
+    Ok(OciImageManifest {
+        schema_version: 2,
+        media_type: Some(IMAGE_MANIFEST_MEDIA_TYPE.to_string()),
+        config: OciDescriptor {
+            media_type: IMAGE_DOCKER_CONFIG_MEDIA_TYPE.to_string(),
+            digest: sha256_digest(&config),
+            size: config.len() as i64,
+            data: Some(base64::engine::general_purpose::STANDARD.encode(&config)),
+            ..Default::default()
+        },
+        layers,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PAYLOAD: &str = r##"{
+   "schemaVersion": 1,
+   "name": "library/hello",
+   "tag": "latest",
+   "architecture": "amd64",
+   "fsLayers": [
+      {
+         "blobSum": "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46a4"
+      },
+      {
+         "blobSum": "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+      }
+   ],
+   "history": [
+      {
+         "v1Compatibility": "{\"id\":\"b\",\"parent\":\"a\",\"created\":\"2016-01-02T00:00:00Z\",\"container_config\":{\"Cmd\":[\"/bin/sh\",\"-c\",\"#(nop) CMD [\\\"/hello\\\"]\"]},\"config\":{\"Cmd\":[\"/hello\"]},\"os\":\"linux\",\"throwaway\":true}"
+      },
+      {
+         "v1Compatibility": "{\"id\":\"a\",\"created\":\"2016-01-01T00:00:00Z\",\"container_config\":{\"Cmd\":[\"/bin/sh\",\"-c\",\"#(nop) COPY file:abc in /\"]}}"
+      }
+   ]
+}"##;
+
+    fn sign(payload: &str) -> String {
+        let b64url = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let format_length = payload.len() - 2;
+        let protected = b64url.encode(format!(
+            r#"{{"formatLength":{},"formatTail":"{}","time":"2016-01-02T00:00:00Z"}}"#,
+            format_length,
+            b64url.encode("\n}")
+        ));
+        format!(
+            r#"{},
+   "signatures": [
+      {{
+         "header": {{ "alg": "ES256" }},
+         "signature": "c2lnbmF0dXJl",
+         "protected": "{}"
+      }}
+   ]
+}}"#,
+            &payload[..format_length],
+            protected
+        )
+    }
+
+    #[test]
+    fn strip_manifest_signatures() {
+        let signed = sign(PAYLOAD);
+        assert_ne!(signed, PAYLOAD);
+        assert_eq!(
+            strip_signatures(signed.as_bytes()).unwrap(),
+            PAYLOAD.as_bytes()
+        );
+        // Unsigned manifests are left untouched
+        assert_eq!(
+            strip_signatures(PAYLOAD.as_bytes()).unwrap(),
+            PAYLOAD.as_bytes()
+        );
+    }
+
+    #[test]
+    fn convert_manifest() {
+        let manifest = convert(PAYLOAD.as_bytes()).unwrap();
+        assert_eq!(
+            manifest.media_type.as_deref(),
+            Some(IMAGE_MANIFEST_MEDIA_TYPE)
+        );
+        // The empty layer is dropped
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(
+            manifest.layers[0].digest,
+            "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+        );
+
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(manifest.config.data.unwrap())
+            .unwrap();
+        assert_eq!(manifest.config.digest, sha256_digest(&data));
+        let config: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(config["architecture"], "amd64");
+        assert_eq!(config["config"]["Cmd"][0], "/hello");
+        assert!(config.get("id").is_none());
+        assert_eq!(
+            config["history"][0]["created_by"],
+            "/bin/sh -c #(nop) COPY file:abc in /"
+        );
+        assert_eq!(config["history"][1]["empty_layer"], true);
+    }
+
+    #[test]
+    fn reject_invalid_manifest() {
+        let payload = PAYLOAD.replace(
+            r#""blobSum": "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46a4""#,
+            r#""blobSum": "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46a4"}, {"blobSum": "sha256:abc""#,
+        );
+        assert!(matches!(
+            convert(payload.as_bytes()),
+            Err(OciDistributionError::ManifestParsingError(_))
+        ));
+    }
+}
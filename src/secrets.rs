@@ -1,5 +1,18 @@
 //! Types for working with registry access secrets
 
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use base64::Engine;
+use docker_credential::DockerCredential;
+use tracing::debug;
+
+use crate::errors::{OciDistributionError, Result};
+
+/// The address used by the docker CLI to store the credentials of Docker Hub
+const DOCKER_HUB_CREDENTIALS_SERVER: &str = "https://index.docker.io/v1/";
+
 /// A method for authenticating to a registry
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum RegistryAuth {
@@ -7,6 +20,331 @@ pub enum RegistryAuth {
     Anonymous,
     /// Access the registry using HTTP Basic authentication
     Basic(String, String),
+    /// Access the registry using an OAuth2 refresh token, such as the
+    /// identity tokens stored by `docker login` for registries using OIDC
+    IdentityToken(String),
+}
+
+impl RegistryAuth {
+    /// Look up the credentials of `registry` inside of the docker
+    /// configuration file.
+    ///
+    /// The file is read from `$DOCKER_CONFIG/config.json`, falling back to
+    /// `~/.docker/config.json`. The credential helper configured for the
+    /// registry inside of `credHelpers`, or the default one configured with
+    /// `credsStore`, is invoked (by running `docker-credential-<name> get`)
+    /// when the credentials are not stored inside of the file itself.
+    ///
+    /// `registry` is usually the value returned by
+    /// [`Reference::resolve_registry`](crate::Reference::resolve_registry).
+    ///
+    /// Returns [`RegistryAuth::Anonymous`] when the configuration file doesn't
+    /// exist or doesn't hold any credential for the registry.
+    ///
+    /// *Note*: this function performs blocking I/O operations.
+    pub fn from_docker_config(registry: &str) -> Result<Self> {
+        Ok(docker_config_credentials(registry)?.unwrap_or(RegistryAuth::Anonymous))
+    }
+}
+
+/// Look up the credentials of `registry` inside of the docker configuration
+/// file, returning `None` when there are none
+fn docker_config_credentials(registry: &str) -> Result<Option<RegistryAuth>> {
+    match docker_config_path() {
+        Some(path) if path.exists() => docker_config_file_credentials(&path, registry),
+        _ => {
+            debug!("Docker configuration file not found");
+            Ok(None)
+        }
+    }
+}
+
+fn docker_config_file_credentials(
+    config_path: &Path,
+    registry: &str,
+) -> Result<Option<RegistryAuth>> {
+    let server = docker_config_server(registry);
+    let reader = std::io::BufReader::new(std::fs::File::open(config_path)?);
+    match docker_credential::get_credential_from_reader(reader, server) {
+        Ok(DockerCredential::UsernamePassword(username, password)) => {
+            debug!(%registry, "Found docker credentials");
+            Ok(Some(RegistryAuth::Basic(username, password)))
+        }
+        Ok(DockerCredential::IdentityToken(token)) => {
+            debug!(%registry, "Found docker identity token");
+            Ok(Some(RegistryAuth::IdentityToken(token)))
+        }
+        Err(docker_credential::CredentialRetrievalError::NoCredentialConfigured) => {
+            debug!(%registry, "No docker credentials configured");
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The path of the docker configuration file
+fn docker_config_path() -> Option<PathBuf> {
+    std::env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))
+        .map(|dir| dir.join("config.json"))
+}
+
+/// A source of registry credentials.
+///
+/// Implementations can be combined using [`ChainedAuthProvider`], which
+/// consults them in priority order until one of them knows about the registry.
+/// The client uses the provider set as
+/// [`ClientConfig::auth_provider`](crate::client::ClientConfig::auth_provider).
+#[async_trait]
+pub trait RegistryAuthProvider: Send + Sync {
+    /// Return the credentials of `registry`, or `None` when the provider
+    /// doesn't have any for it.
+    ///
+    /// `registry` is usually the value returned by
+    /// [`Reference::resolve_registry`](crate::Reference::resolve_registry).
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>>;
+
+    /// Return the credentials of `registry`, falling back to anonymous access
+    /// when the provider doesn't have any for it.
+    async fn resolve(&self, registry: &str) -> Result<RegistryAuth> {
+        Ok(self
+            .credentials(registry)
+            .await?
+            .unwrap_or(RegistryAuth::Anonymous))
+    }
+}
+
+/// Provides a fixed set of credentials, such as the secrets configured for a
+/// workload.
+#[derive(Debug, Clone, Default)]
+pub struct StaticAuthProvider {
+    credentials: HashMap<String, RegistryAuth>,
+}
+
+impl StaticAuthProvider {
+    /// Create a provider without any credentials
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the credentials of `registry` to the provider
+    pub fn with_credentials(mut self, registry: impl Into<String>, auth: RegistryAuth) -> Self {
+        let registry = registry.into();
+        self.credentials
+            .insert(docker_config_registry(&registry).to_string(), auth);
+        self
+    }
+
+    /// Create a provider holding the credentials of a Kubernetes image pull
+    /// secret, given the content of its `.dockerconfigjson` key, or of the
+    /// `.dockercfg` key of legacy secrets.
+    ///
+    /// The servers of the secret are reduced to their host, so that they
+    /// match the value returned by
+    /// [`Reference::resolve_registry`](crate::Reference::resolve_registry).
+    /// When several servers designate the same registry, such as `docker.io`
+    /// and `https://index.docker.io/v1/`, the credentials of the server docker
+    /// itself would use are kept, then the ones of the first server in
+    /// alphabetical order. Combine the providers of several secrets with a
+    /// [`ChainedAuthProvider`] to give precedence to the first ones.
+    pub fn from_docker_config_json(data: &[u8]) -> Result<Self> {
+        let config: DockerConfigJson = serde_json::from_slice(data)?;
+        let auths = match config {
+            DockerConfigJson::Config { auths } => auths,
+            DockerConfigJson::Legacy(auths) => auths,
+        };
+        let mut auths: Vec<_> = auths.into_iter().collect();
+        auths.sort_by(|(a, _), (b, _)| {
+            let key = |server: &String| (server != docker_config_server(server), server.clone());
+            key(a).cmp(&key(b))
+        });
+
+        let mut provider = Self::new();
+        for (server, entry) in auths {
+            let auth = match (
+                entry.identitytoken,
+                entry.username,
+                entry.password,
+                entry.auth,
+            ) {
+                (Some(token), ..) if !token.is_empty() => RegistryAuth::IdentityToken(token),
+                (_, Some(username), Some(password), _) => RegistryAuth::Basic(username, password),
+                (_, _, _, Some(auth)) => decode_docker_auth(&auth)?,
+                _ => {
+                    debug!(%server, "Ignoring docker config entry without credentials");
+                    continue;
+                }
+            };
+            provider
+                .credentials
+                .entry(docker_config_registry(&server).to_string())
+                .or_insert(auth);
+        }
+        Ok(provider)
+    }
+}
+
+/// The content of a `.dockerconfigjson` or `.dockercfg` image pull secret
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum DockerConfigJson {
+    Config {
+        auths: HashMap<String, DockerConfigEntry>,
+    },
+    Legacy(HashMap<String, DockerConfigEntry>),
+}
+
+#[derive(serde::Deserialize)]
+struct DockerConfigEntry {
+    username: Option<String>,
+    password: Option<String>,
+    auth: Option<String>,
+    identitytoken: Option<String>,
+}
+
+/// Decode the base64 encoded `username:password` of a docker config entry
+fn decode_docker_auth(auth: &str) -> Result<RegistryAuth> {
+    let invalid = || OciDistributionError::GenericError(Some("invalid docker auth".to_string()));
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(auth)
+        .map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (username, password) = decoded.split_once(':').ok_or_else(invalid)?;
+    Ok(RegistryAuth::Basic(
+        username.to_string(),
+        password.to_string(),
+    ))
+}
+
+/// Return the registry of a server of a docker config, such as
+/// `index.docker.io` for `https://index.docker.io/v1/`: the scheme and path
+/// are removed, and the aliases of Docker Hub are resolved
+fn docker_config_registry(server: &str) -> &str {
+    let host = server
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    match host {
+        "docker.io" | "registry-1.docker.io" => "index.docker.io",
+        host => host,
+    }
+}
+
+/// Return the server docker stores the credentials of `registry` under
+fn docker_config_server(registry: &str) -> &str {
+    match docker_config_registry(registry) {
+        "index.docker.io" => DOCKER_HUB_CREDENTIALS_SERVER,
+        host => host,
+    }
+}
+
+#[async_trait]
+impl RegistryAuthProvider for StaticAuthProvider {
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        Ok(self
+            .credentials
+            .get(docker_config_registry(registry))
+            .cloned())
+    }
+}
+
+/// Provides the credentials stored inside of the docker configuration file.
+///
+/// See [`RegistryAuth::from_docker_config`] for details about how the
+/// credentials are looked up. The file is read, and the credential helpers
+/// are run, on the blocking thread pool of the tokio runtime.
+#[derive(Debug, Clone, Default)]
+pub struct DockerConfigAuthProvider {
+    config_path: Option<PathBuf>,
+}
+
+impl DockerConfigAuthProvider {
+    /// Create a provider reading the default docker configuration file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a provider reading the docker configuration file found at
+    /// `config_path`
+    pub fn from_file(config_path: impl Into<PathBuf>) -> Self {
+        DockerConfigAuthProvider {
+            config_path: Some(config_path.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl RegistryAuthProvider for DockerConfigAuthProvider {
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        // Reading the file and running the credential helpers blocks
+        let config_path = self.config_path.clone();
+        let registry = registry.to_string();
+        tokio::task::spawn_blocking(move || match config_path {
+            Some(path) => docker_config_file_credentials(&path, &registry),
+            None => docker_config_credentials(&registry),
+        })
+        .await
+        .map_err(|e| OciDistributionError::GenericError(Some(e.to_string())))?
+    }
+}
+
+/// Provides anonymous access to every registry. This is usually the last
+/// provider of a [`ChainedAuthProvider`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnonymousAuthProvider;
+
+#[async_trait]
+impl RegistryAuthProvider for AnonymousAuthProvider {
+    async fn credentials(&self, _registry: &str) -> Result<Option<RegistryAuth>> {
+        Ok(Some(RegistryAuth::Anonymous))
+    }
+}
+
+/// Consults a list of providers in priority order, returning the credentials
+/// of the first one knowing about the registry.
+///
+/// ```rust
+/// # use oci_distribution::secrets::*;
+/// let provider = ChainedAuthProvider::new()
+///     .with_provider(StaticAuthProvider::new().with_credentials(
+///         "myregistry.example.com",
+///         RegistryAuth::Basic("user".to_string(), "password".to_string()),
+///     ))
+///     .with_provider(DockerConfigAuthProvider::new())
+///     .with_provider(AnonymousAuthProvider);
+/// ```
+#[derive(Default)]
+pub struct ChainedAuthProvider {
+    providers: Vec<Box<dyn RegistryAuthProvider>>,
+}
+
+impl ChainedAuthProvider {
+    /// Create an empty chain
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a provider to the chain, with a lower priority than the ones
+    /// already added
+    pub fn with_provider(mut self, provider: impl RegistryAuthProvider + 'static) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+}
+
+#[async_trait]
+impl RegistryAuthProvider for ChainedAuthProvider {
+    async fn credentials(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        for provider in &self.providers {
+            if let Some(auth) = provider.credentials(registry).await? {
+                return Ok(Some(auth));
+            }
+        }
+        Ok(None)
+    }
 }
 
 pub(crate) trait Authenticable {
@@ -18,6 +356,153 @@ impl Authenticable for reqwest::RequestBuilder {
         match auth {
             RegistryAuth::Anonymous => self,
             RegistryAuth::Basic(username, password) => self.basic_auth(username, Some(password)),
+            // Identity tokens can only be exchanged for bearer tokens
+            RegistryAuth::IdentityToken(_) => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOCKER_CONFIG: &str = r#"{
+        "auths": {
+            "https://index.docker.io/v1/": {
+                "auth": "aHViLXVzZXI6aHViLXBhc3N3b3Jk"
+            },
+            "myregistry.example.com": {
+                "auth": "dXNlcjpwYXNzd29yZA=="
+            },
+            "oidc.example.com": {
+                "auth": "PHRva2VuPjo=",
+                "identitytoken": "refresh-token"
+            }
         }
+    }"#;
+
+    #[test]
+    fn credentials_from_docker_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, DOCKER_CONFIG).unwrap();
+
+        assert_eq!(
+            docker_config_file_credentials(&path, "myregistry.example.com").unwrap(),
+            Some(RegistryAuth::Basic(
+                "user".to_string(),
+                "password".to_string()
+            ))
+        );
+        assert_eq!(
+            docker_config_file_credentials(&path, "index.docker.io").unwrap(),
+            Some(RegistryAuth::Basic(
+                "hub-user".to_string(),
+                "hub-password".to_string()
+            ))
+        );
+        assert_eq!(
+            docker_config_file_credentials(&path, "oidc.example.com").unwrap(),
+            Some(RegistryAuth::IdentityToken("refresh-token".to_string()))
+        );
+        assert_eq!(
+            docker_config_file_credentials(&path, "ghcr.io").unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn chained_providers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, DOCKER_CONFIG).unwrap();
+
+        let provider = ChainedAuthProvider::new()
+            .with_provider(StaticAuthProvider::new().with_credentials(
+                "myregistry.example.com",
+                RegistryAuth::Basic("static-user".to_string(), "static-password".to_string()),
+            ))
+            .with_provider(DockerConfigAuthProvider::from_file(path));
+
+        // Static secrets take precedence over the docker configuration
+        assert_eq!(
+            provider.resolve("myregistry.example.com").await.unwrap(),
+            RegistryAuth::Basic("static-user".to_string(), "static-password".to_string())
+        );
+        assert_eq!(
+            provider.resolve("docker.io").await.unwrap(),
+            RegistryAuth::Basic("hub-user".to_string(), "hub-password".to_string())
+        );
+        assert_eq!(provider.credentials("ghcr.io").await.unwrap(), None);
+        assert_eq!(
+            provider.resolve("ghcr.io").await.unwrap(),
+            RegistryAuth::Anonymous
+        );
+
+        let provider = provider.with_provider(AnonymousAuthProvider);
+        assert_eq!(
+            provider.credentials("ghcr.io").await.unwrap(),
+            Some(RegistryAuth::Anonymous)
+        );
+    }
+
+    #[tokio::test]
+    async fn credentials_from_image_pull_secret() {
+        let provider =
+            StaticAuthProvider::from_docker_config_json(DOCKER_CONFIG.as_bytes()).unwrap();
+        assert_eq!(
+            provider.credentials("index.docker.io").await.unwrap(),
+            Some(RegistryAuth::Basic(
+                "hub-user".to_string(),
+                "hub-password".to_string()
+            ))
+        );
+        assert_eq!(
+            provider.credentials("oidc.example.com").await.unwrap(),
+            Some(RegistryAuth::IdentityToken("refresh-token".to_string()))
+        );
+        assert_eq!(provider.credentials("ghcr.io").await.unwrap(), None);
+
+        let legacy = r#"{
+            "https://myregistry.example.com:5000/v1/": {
+                "username": "user",
+                "password": "password"
+            }
+        }"#;
+        let provider = StaticAuthProvider::from_docker_config_json(legacy.as_bytes()).unwrap();
+        assert_eq!(
+            provider
+                .credentials("myregistry.example.com:5000")
+                .await
+                .unwrap(),
+            Some(RegistryAuth::Basic(
+                "user".to_string(),
+                "password".to_string()
+            ))
+        );
+
+        // The server docker would use wins over its aliases
+        let aliases = r#"{"auths": {
+            "docker.io": {"username": "alias", "password": "password"},
+            "https://index.docker.io/v1/": {"username": "docker", "password": "password"},
+            "registry-1.docker.io": {"username": "other-alias", "password": "password"},
+            "https://ghcr.io": {"username": "alias", "password": "password"},
+            "ghcr.io": {"username": "docker", "password": "password"}
+        }}"#;
+        let provider = StaticAuthProvider::from_docker_config_json(aliases.as_bytes()).unwrap();
+        for registry in ["index.docker.io", "docker.io", "ghcr.io"] {
+            assert_eq!(
+                provider.credentials(registry).await.unwrap(),
+                Some(RegistryAuth::Basic(
+                    "docker".to_string(),
+                    "password".to_string()
+                )),
+                "{}",
+                registry
+            );
+        }
+
+        let invalid = r#"{"auths": {"ghcr.io": {"auth": "bm8tY29sb24="}}}"#;
+        assert!(StaticAuthProvider::from_docker_config_json(invalid.as_bytes()).is_err());
     }
 }
@@ -0,0 +1,394 @@
+//! Verification of [cosign](https://github.com/sigstore/cosign) image signatures
+//!
+//! Cosign stores the signatures of an image inside of the registry, next to
+//! the image itself: either in a manifest tagged `<alg>-<hex>.sig` after the
+//! digest of the image, or in an artifact referring to the image, which can be
+//! found through the referrers API. Each signature is a layer holding a
+//! "simple signing" payload, which names the digest of the signed manifest,
+//! while the signature of the payload is stored inside of the layer
+//! annotations.
+//!
+//! [`Client::verify_signature`] looks for these signatures and accepts the
+//! image when one of them has been produced by one of the public keys of a
+//! [`SignaturePolicy`]. Only signatures made with ECDSA P-256 keys, the
+//! default of `cosign generate-key-pair`, are supported. Keyless signatures,
+//! relying on Fulcio certificates and Rekor transparency log entries, are not.
+//!
+//! This module is available when the `sigstore` feature is enabled.
+
+use std::collections::HashMap;
+
+use base64::Engine;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey;
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::client::Client;
+use crate::errors::{OciDistributionError, Result};
+use crate::manifest::OciImageManifest;
+use crate::secrets::RegistryAuth;
+use crate::Reference;
+
+/// The media type of the layers holding cosign signatures
+pub const COSIGN_SIMPLE_SIGNING_MEDIA_TYPE: &str =
+    "application/vnd.dev.cosign.simplesigning.v1+json";
+/// The artifact type of cosign signatures stored as referrers
+pub const COSIGN_SIGNATURE_ARTIFACT_TYPE: &str = "application/vnd.dev.cosign.artifact.sig.v1+json";
+/// The annotation holding the base64 encoded signature of a layer payload
+pub const COSIGN_SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// The type of the payloads signed by cosign
+const COSIGN_PAYLOAD_TYPE: &str = "cosign container image signature";
+
+/// The public keys an image must be signed with to be trusted
+#[derive(Debug, Clone, Default)]
+pub struct SignaturePolicy {
+    keys: Vec<VerifyingKey>,
+}
+
+impl SignaturePolicy {
+    /// Create a policy without any key, which doesn't trust any image
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the images signed with the given PEM encoded public key, as
+    /// generated by `cosign generate-key-pair`
+    pub fn with_public_key_pem(mut self, pem: &str) -> Result<Self> {
+        let key = VerifyingKey::from_public_key_pem(pem).map_err(|e| {
+            OciDistributionError::SignatureVerificationError(format!(
+                "cannot parse public key: {}",
+                e
+            ))
+        })?;
+        self.keys.push(key);
+        Ok(self)
+    }
+}
+
+/// A signature that has been verified against a [`SignaturePolicy`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedSignature {
+    /// The digest of the signed manifest
+    pub manifest_digest: String,
+    /// The reference of the image, as recorded by the signer
+    pub docker_reference: String,
+    /// The annotations added by the signer, such as with `cosign sign -a`
+    pub annotations: Option<HashMap<String, serde_json::Value>>,
+}
+
+/// The simple signing payload signed by cosign
+#[derive(Deserialize)]
+struct SimpleSigning {
+    critical: Critical,
+    optional: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct Critical {
+    identity: Identity,
+    image: Image,
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+#[derive(Deserialize)]
+struct Identity {
+    #[serde(rename = "docker-reference")]
+    docker_reference: String,
+}
+
+#[derive(Deserialize)]
+struct Image {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+impl Client {
+    /// Verify that the given Reference has been signed with cosign, using
+    /// one of the keys of `policy`
+    ///
+    /// The client will check if it's already been authenticated and if
+    /// not will attempt to do.
+    ///
+    /// The signatures are looked up using both the `.sig` tag used by cosign
+    /// and the referrers API. This is meant to be called before running an
+    /// image: the image must then be pulled by the digest returned in
+    /// [`VerifiedSignature::manifest_digest`], since tags can be moved.
+    pub async fn verify_signature(
+        &mut self,
+        image: &Reference,
+        auth: &RegistryAuth,
+        policy: &SignaturePolicy,
+    ) -> Result<VerifiedSignature> {
+        if policy.keys.is_empty() {
+            return Err(OciDistributionError::SignatureVerificationError(
+                "the signature policy doesn't trust any key".to_string(),
+            ));
+        }
+
+        let digest = match image.digest() {
+            Some(digest) => digest.to_string(),
+            None => self.fetch_manifest_digest(image, auth).await?,
+        };
+
+        let mut signature_manifests = Vec::new();
+        let signature_tag = Reference::with_tag(
+            image.registry().to_string(),
+            image.repository().to_string(),
+            format!("{}.sig", digest.replacen(':', "-", 1)),
+        );
+        match self.pull_image_manifest(&signature_tag, auth).await {
+            Ok((manifest, _)) => signature_manifests.push((signature_tag, manifest)),
+            Err(e) => debug!(error = ?e, "No signature found using the cosign tag"),
+        }
+        let referrers = match self
+            .list_referrers(image, auth, Some(COSIGN_SIGNATURE_ARTIFACT_TYPE))
+            .await
+        {
+            Ok(referrers) => referrers,
+            Err(e) => {
+                debug!(error = ?e, "Cannot list the referrers of the image");
+                Vec::new()
+            }
+        };
+        // A signature that can't be downloaded doesn't prevent the other ones
+        // from being verified
+        let mut failures = Vec::new();
+        for referrer in referrers {
+            let reference = Reference::with_digest(
+                image.registry().to_string(),
+                image.repository().to_string(),
+                referrer.digest,
+            );
+            match self.pull_image_manifest(&reference, auth).await {
+                Ok((manifest, _)) => signature_manifests.push((reference, manifest)),
+                Err(e) => failures.push(format!("cannot pull {}: {}", reference, e)),
+            }
+        }
+
+        for (reference, manifest) in &signature_manifests {
+            if let Some(verified) = self
+                .verify_signature_manifest(reference, manifest, &digest, policy, &mut failures)
+                .await
+            {
+                return Ok(verified);
+            }
+        }
+
+        if failures.is_empty() {
+            failures.push("the image is not signed".to_string());
+        }
+        Err(OciDistributionError::SignatureVerificationError(format!(
+            "no valid signature found for {}: {}",
+            image,
+            failures.join("; ")
+        )))
+    }
+
+    /// Look for a layer of a signature manifest holding a valid signature,
+    /// recording why the other layers were rejected in `failures`
+    async fn verify_signature_manifest(
+        &self,
+        reference: &Reference,
+        manifest: &OciImageManifest,
+        digest: &str,
+        policy: &SignaturePolicy,
+        failures: &mut Vec<String>,
+    ) -> Option<VerifiedSignature> {
+        for layer in &manifest.layers {
+            if layer.media_type != COSIGN_SIMPLE_SIGNING_MEDIA_TYPE {
+                continue;
+            }
+            let signature = match layer
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get(COSIGN_SIGNATURE_ANNOTATION))
+            {
+                Some(signature) => signature,
+                None => continue,
+            };
+
+            let mut payload = Vec::new();
+            if let Err(e) = self.pull_blob(reference, &layer.digest, &mut payload).await {
+                failures.push(format!("cannot pull {}: {}", layer.digest, e));
+                continue;
+            }
+            match verify_payload(policy, &payload, signature, digest) {
+                Ok(verified) => return Some(verified),
+                Err(e) => {
+                    warn!(error = %e, layer = %layer.digest, "Ignoring invalid signature");
+                    failures.push(e.to_string());
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Verify the signature of a simple signing payload, and that the payload
+/// describes the manifest with the expected digest
+fn verify_payload(
+    policy: &SignaturePolicy,
+    payload: &[u8],
+    signature: &str,
+    expected_digest: &str,
+) -> Result<VerifiedSignature> {
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature)
+        .ok()
+        .and_then(|der| Signature::from_der(&der).ok())
+        .ok_or_else(|| {
+            OciDistributionError::SignatureVerificationError("cannot decode signature".to_string())
+        })?;
+    if !policy
+        .keys
+        .iter()
+        .any(|key| key.verify(payload, &signature).is_ok())
+    {
+        return Err(OciDistributionError::SignatureVerificationError(
+            "the signature doesn't match any trusted key".to_string(),
+        ));
+    }
+
+    let payload: SimpleSigning = serde_json::from_slice(payload).map_err(|e| {
+        OciDistributionError::SignatureVerificationError(format!(
+            "cannot parse signature payload: {}",
+            e
+        ))
+    })?;
+    if payload.critical.type_ != COSIGN_PAYLOAD_TYPE {
+        return Err(OciDistributionError::SignatureVerificationError(format!(
+            "unexpected signature payload type {}",
+            payload.critical.type_
+        )));
+    }
+    if payload.critical.image.docker_manifest_digest != expected_digest {
+        return Err(OciDistributionError::SignatureVerificationError(format!(
+            "the signature is for {}, not {}",
+            payload.critical.image.docker_manifest_digest, expected_digest
+        )));
+    }
+
+    Ok(VerifiedSignature {
+        manifest_digest: payload.critical.image.docker_manifest_digest,
+        docker_reference: payload.critical.identity.docker_reference,
+        annotations: payload.optional,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+
+    const DIGEST: &str = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32].into()).unwrap()
+    }
+
+    fn policy(key: &SigningKey) -> SignaturePolicy {
+        let pem = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        SignaturePolicy::new().with_public_key_pem(&pem).unwrap()
+    }
+
+    fn sign(key: &SigningKey, payload: &[u8]) -> String {
+        let signature: Signature = key.sign(payload);
+        base64::engine::general_purpose::STANDARD.encode(signature.to_der())
+    }
+
+    fn payload(digest: &str) -> Vec<u8> {
+        format!(
+            r#"{{"critical":{{"identity":{{"docker-reference":"ghcr.io/krustlet/hello"}},"image":{{"docker-manifest-digest":"{}"}},"type":"cosign container image signature"}},"optional":{{"env":"prod"}}}}"#,
+            digest
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn verify_valid_signature() {
+        let key = signing_key(1);
+        let payload = payload(DIGEST);
+        let verified = verify_payload(&policy(&key), &payload, &sign(&key, &payload), DIGEST)
+            .expect("valid signature");
+        assert_eq!(verified.manifest_digest, DIGEST);
+        assert_eq!(verified.docker_reference, "ghcr.io/krustlet/hello");
+        assert_eq!(
+            verified.annotations.unwrap()["env"],
+            serde_json::Value::String("prod".to_string())
+        );
+    }
+
+    #[test]
+    fn reject_untrusted_key() {
+        let payload = payload(DIGEST);
+        let signature = sign(&signing_key(2), &payload);
+        assert!(verify_payload(&policy(&signing_key(1)), &payload, &signature, DIGEST).is_err());
+    }
+
+    #[test]
+    fn reject_signature_of_other_image() {
+        let key = signing_key(1);
+        let payload =
+            payload("sha256:0000000000000000000000000000000000000000000000000000000000000000");
+        let signature = sign(&key, &payload);
+        assert!(verify_payload(&policy(&key), &payload, &signature, DIGEST).is_err());
+    }
+
+    #[tokio::test]
+    async fn skip_signatures_that_cannot_be_pulled() {
+        let key = signing_key(1);
+        let payload = payload(DIGEST);
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"{}","digest":"{}","size":2}},"layers":[{{"mediaType":"{}","digest":"{}","size":{},"annotations":{{"{}":"{}"}}}}]}}"#,
+            crate::manifest::OCI_IMAGE_MEDIA_TYPE,
+            crate::manifest::OCI_EMPTY_CONFIG_MEDIA_TYPE,
+            crate::sha256_digest(b"{}"),
+            COSIGN_SIMPLE_SIGNING_MEDIA_TYPE,
+            crate::sha256_digest(&payload),
+            payload.len(),
+            COSIGN_SIGNATURE_ANNOTATION,
+            sign(&key, &payload)
+        );
+        let unavailable = crate::sha256_digest(b"unavailable");
+        let index = format!(
+            r#"{{"schemaVersion":2,"manifests":[{{"mediaType":"{media_type}","digest":"{}","size":1,"artifactType":"{artifact_type}"}},{{"mediaType":"{media_type}","digest":"{}","size":{},"artifactType":"{artifact_type}"}}]}}"#,
+            unavailable,
+            crate::sha256_digest(manifest.as_bytes()),
+            manifest.len(),
+            media_type = crate::manifest::OCI_IMAGE_MEDIA_TYPE,
+            artifact_type = COSIGN_SIGNATURE_ARTIFACT_TYPE,
+        );
+        let (addr, _server) = crate::client::test::serve_http(vec![
+            (200, Vec::new()),
+            // No signature under the cosign tag
+            (404, Vec::new()),
+            (200, index.into_bytes()),
+            // The first signature can't be pulled
+            (404, Vec::new()),
+            (200, manifest.into_bytes()),
+            (200, payload.clone()),
+        ]);
+
+        let mut client = Client::try_from(crate::client::ClientConfig {
+            protocol: crate::client::ClientProtocol::Http,
+            retry: crate::retry::RetryPolicy::disabled(),
+            ..Default::default()
+        })
+        .unwrap();
+        let image = Reference::try_from(format!("{}/hello@{}", addr, DIGEST)).unwrap();
+        let verified = client
+            .verify_signature(&image, &RegistryAuth::Anonymous, &policy(&key))
+            .await
+            .expect("valid signature");
+        assert_eq!(verified.manifest_digest, DIGEST);
+    }
+}
@@ -3,7 +3,20 @@ use serde::Deserialize;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::{debug, warn};
+use tracing::debug;
+
+/// Tokens are refreshed this many seconds before their actual expiration, so
+/// that they don't expire while an operation is in flight. Until then, they
+/// are still used to authenticate requests.
+const EXPIRATION_MARGIN_SECS: u64 = 10;
+
+/// The validity assumed for tokens that don't state their expiration, as
+/// indicated here: https://docs.docker.com/registry/spec/auth/token/#requesting-a-token
+/// > (Optional) The duration in seconds since the token was issued
+/// > that it will remain valid. When omitted, this defaults to 60 seconds.
+/// > For compatibility with older clients, a token should never be returned
+/// > with less than 60 seconds to live.
+const DEFAULT_TOKEN_VALIDITY_SECS: u64 = 60;
 
 /// A token granted during the OAuth2-like workflow for OCI registries.
 #[derive(Deserialize, Clone)]
@@ -29,6 +42,17 @@ impl fmt::Debug for RegistryToken {
     }
 }
 
+/// The response of a token endpoint
+#[derive(Deserialize, Debug)]
+pub(crate) struct RegistryTokenResponse {
+    #[serde(flatten)]
+    pub token: RegistryToken,
+    /// The validity of the token, in seconds
+    pub expires_in: Option<u64>,
+    /// A token that can be exchanged for new tokens using the OAuth2 flow
+    pub refresh_token: Option<String>,
+}
+
 #[derive(Debug)]
 pub(crate) enum RegistryTokenType {
     Bearer(RegistryToken),
@@ -55,12 +79,41 @@ pub enum RegistryOperation {
     Push,
     /// Authenticate for pull operations
     Pull,
+    /// Authenticate for listing the repositories of a registry
+    Catalog,
+}
+
+/// What a token grants access to: a repository of a registry, or the
+/// registry itself for operations such as listing its catalog
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TokenTarget<'a> {
+    pub(crate) registry: &'a str,
+    pub(crate) repository: Option<&'a str>,
+}
+
+impl<'a> TokenTarget<'a> {
+    /// The registry itself, rather than one of its repositories
+    pub(crate) fn registry(registry: &'a str) -> Self {
+        TokenTarget {
+            registry,
+            repository: None,
+        }
+    }
+}
+
+impl<'a> From<&'a Reference> for TokenTarget<'a> {
+    fn from(reference: &'a Reference) -> Self {
+        TokenTarget {
+            registry: reference.resolve_registry(),
+            repository: Some(reference.repository()),
+        }
+    }
 }
 
 #[derive(Default)]
 pub(crate) struct TokenCache {
     // (registry, repository, scope) -> (token, expiration)
-    tokens: BTreeMap<(String, String, RegistryOperation), (RegistryTokenType, u64)>,
+    tokens: BTreeMap<(String, Option<String>, RegistryOperation), (RegistryTokenType, u64)>,
 }
 
 impl TokenCache {
@@ -70,85 +123,214 @@ impl TokenCache {
         }
     }
 
-    pub(crate) fn insert(
+    /// Store a token, `expires_in` being the validity in seconds reported
+    /// by the token endpoint, if any.
+    pub(crate) fn insert<'a>(
         &mut self,
-        reference: &Reference,
+        target: impl Into<TokenTarget<'a>>,
         op: RegistryOperation,
         token: RegistryTokenType,
+        expires_in: Option<u64>,
     ) {
         let expiration = match token {
             RegistryTokenType::Basic(_, _) => u64::MAX,
-            RegistryTokenType::Bearer(ref t) => {
-                let token_str = t.token();
-                match jwt::Token::<
+            RegistryTokenType::Bearer(ref t) => match expires_in {
+                Some(expires_in) => now_secs().saturating_add(expires_in),
+                None => {
+                    // Tokens are not required to be JWTs, in which case their
+                    // expiration cannot be extracted from their claims
+                    match jwt::Token::<
                         jwt::header::Header,
                         jwt::claims::Claims,
                         jwt::token::Unverified,
-                    >::parse_unverified(token_str)
+                    >::parse_unverified(t.token())
                     {
                         Ok(token) => token.claims().registered.expiration.unwrap_or(u64::MAX),
-                        Err(jwt::Error::NoClaimsComponent) => {
-                            // the token doesn't have a claim that states a
-                            // value for the expiration. We assume it has a 60
-                            // seconds validity as indicated here:
-                            // https://docs.docker.com/registry/spec/auth/token/#requesting-a-token
-                            // > (Optional) The duration in seconds since the token was issued
-                            // > that it will remain valid. When omitted, this defaults to 60 seconds.
-                            // > For compatibility with older clients, a token should never be returned
-                            // > with less than 60 seconds to live.
-                            let now = SystemTime::now();
-                            let epoch = now
-                                .duration_since(UNIX_EPOCH)
-                                .expect("Time went backwards")
-                                .as_secs();
-                            let expiration = epoch + 60;
-                            debug!(?token, "Cannot extract expiration from token's claims, assuming a 60 seconds validity");
-                            expiration
-                        },
-                        Err(error) => {
-                            warn!(?error, "Invalid bearer token");
-                            return;
+                        Err(_) => {
+                            debug!(
+                                ?token,
+                                "Cannot find token expiration, assuming a 60 seconds validity"
+                            );
+                            now_secs() + DEFAULT_TOKEN_VALIDITY_SECS
                         }
                     }
-            }
+                }
+            },
         };
-        let registry = reference.resolve_registry().to_string();
-        let repository = reference.repository().to_string();
-        debug!(%registry, %repository, ?op, %expiration, "Inserting token");
+        let target = target.into();
+        let registry = target.registry.to_string();
+        let repository = target.repository.map(str::to_string);
+        debug!(%registry, ?repository, ?op, %expiration, "Inserting token");
         self.tokens
             .insert((registry, repository, op), (token, expiration));
     }
 
-    pub(crate) fn get(
+    /// Return the token for `op`, unless it has expired. A push token is
+    /// returned for pull operations when there is no pull token, since it
+    /// grants both.
+    pub(crate) fn get<'a>(
         &self,
-        reference: &Reference,
+        target: impl Into<TokenTarget<'a>>,
         op: RegistryOperation,
     ) -> Option<&RegistryTokenType> {
-        let registry = reference.resolve_registry().to_string();
-        let repository = reference.repository().to_string();
-        match self.tokens.get(&(registry.clone(), repository.clone(), op)) {
+        self.lookup(target.into(), op, 0)
+    }
+
+    /// Return `true` when a token for `op` is available and is not about to
+    /// expire, in which case there is no need to authenticate again.
+    pub(crate) fn contains_key<'a>(
+        &self,
+        target: impl Into<TokenTarget<'a>>,
+        op: RegistryOperation,
+    ) -> bool {
+        self.lookup(target.into(), op, EXPIRATION_MARGIN_SECS)
+            .is_some()
+    }
+
+    fn lookup(
+        &self,
+        target: TokenTarget,
+        op: RegistryOperation,
+        margin: u64,
+    ) -> Option<&RegistryTokenType> {
+        let token = self.get_valid(target, op, margin);
+        match (token, op) {
+            (None, RegistryOperation::Pull) => {
+                self.get_valid(target, RegistryOperation::Push, margin)
+            }
+            (token, _) => token,
+        }
+    }
+
+    /// Return the token for `op`, unless it expires within `margin` seconds
+    fn get_valid(
+        &self,
+        target: TokenTarget,
+        op: RegistryOperation,
+        margin: u64,
+    ) -> Option<&RegistryTokenType> {
+        let TokenTarget {
+            registry,
+            repository,
+        } = target;
+        match self
+            .tokens
+            .get(&(registry.to_string(), repository.map(str::to_string), op))
+        {
             Some((ref token, expiration)) => {
-                let now = SystemTime::now();
-                let epoch = now
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Time went backwards")
-                    .as_secs();
-                if epoch > *expiration {
-                    debug!(%registry, %repository, ?op, %expiration, miss=false, expired=true, "Fetching token");
+                if now_secs().saturating_add(margin) > *expiration {
+                    debug!(%registry, ?repository, ?op, %expiration, miss=false, expired=true, "Fetching token");
                     None
                 } else {
-                    debug!(%registry, %repository, ?op, %expiration, miss=false, expired=false, "Fetching token");
+                    debug!(%registry, ?repository, ?op, %expiration, miss=false, expired=false, "Fetching token");
                     Some(token)
                 }
             }
             None => {
-                debug!(%registry, %repository, ?op, miss=true, "Fetching token");
+                debug!(%registry, ?repository, ?op, miss=true, "Fetching token");
                 None
             }
         }
     }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bearer(token: &str) -> RegistryTokenType {
+        RegistryTokenType::Bearer(RegistryToken::Token {
+            token: token.to_string(),
+        })
+    }
+
+    #[test]
+    fn opaque_token_expiration() {
+        let reference: Reference = "ghcr.io/krustlet/hello:v1".parse().unwrap();
+        let mut cache = TokenCache::new();
+
+        // Opaque tokens, which are not JWTs, are cached as well
+        cache.insert(&reference, RegistryOperation::Pull, bearer("opaque"), None);
+        assert!(cache.contains_key(&reference, RegistryOperation::Pull));
+
+        cache.insert(
+            &reference,
+            RegistryOperation::Pull,
+            bearer("opaque"),
+            Some(300),
+        );
+        assert!(cache.contains_key(&reference, RegistryOperation::Pull));
+
+        // Tokens about to expire are refreshed, but are still used to
+        // authenticate requests until they expire
+        cache.insert(
+            &reference,
+            RegistryOperation::Pull,
+            bearer("opaque"),
+            Some(EXPIRATION_MARGIN_SECS / 2),
+        );
+        assert!(!cache.contains_key(&reference, RegistryOperation::Pull));
+        assert!(cache.get(&reference, RegistryOperation::Pull).is_some());
+    }
+
+    #[test]
+    fn jwt_without_expiration() {
+        let reference: Reference = "ghcr.io/krustlet/hello:v1".parse().unwrap();
+        let mut cache = TokenCache::new();
+
+        // {"alg":"HS256"}.{}
+        cache.insert(
+            &reference,
+            RegistryOperation::Pull,
+            bearer("eyJhbGciOiJIUzI1NiJ9.e30.c2ln"),
+            None,
+        );
+        let expirations: Vec<_> = cache.tokens.values().map(|(_, e)| *e).collect();
+        assert_eq!(expirations, vec![u64::MAX]);
+    }
+
+    #[test]
+    fn push_token_grants_pull() {
+        let reference: Reference = "ghcr.io/krustlet/hello:v1".parse().unwrap();
+        let other: Reference = "ghcr.io/krustlet/other:v1".parse().unwrap();
+        let mut cache = TokenCache::new();
+
+        cache.insert(&reference, RegistryOperation::Push, bearer("push"), None);
+        assert!(cache.contains_key(&reference, RegistryOperation::Pull));
+        assert!(cache.contains_key(&reference, RegistryOperation::Push));
+        assert!(!cache.contains_key(&other, RegistryOperation::Pull));
+
+        cache.insert(&reference, RegistryOperation::Pull, bearer("pull"), None);
+        match cache.get(&reference, RegistryOperation::Pull) {
+            Some(RegistryTokenType::Bearer(token)) => assert_eq!(token.token(), "pull"),
+            other => panic!("unexpected token: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn registry_tokens() {
+        let reference: Reference = "ghcr.io/krustlet/hello:v1".parse().unwrap();
+        let registry = TokenTarget::registry("ghcr.io");
+        let mut cache = TokenCache::new();
+
+        cache.insert(
+            registry,
+            RegistryOperation::Catalog,
+            bearer("catalog"),
+            None,
+        );
+        assert!(cache.contains_key(registry, RegistryOperation::Catalog));
+        // Registry tokens don't grant access to the repositories
+        assert!(!cache.contains_key(&reference, RegistryOperation::Catalog));
 
-    pub(crate) fn contains_key(&self, reference: &Reference, op: RegistryOperation) -> bool {
-        self.get(reference, op).is_some()
+        cache.insert(&reference, RegistryOperation::Pull, bearer("pull"), None);
+        assert!(!cache.contains_key(registry, RegistryOperation::Pull));
     }
 }